@@ -0,0 +1,89 @@
+#[macro_use]
+extern crate criterion;
+#[macro_use]
+extern crate slog;
+extern crate slog_journald;
+
+use criterion::Criterion;
+use slog::{Drain, Logger};
+use slog_journald::JournaldDrain;
+
+fn bench_message_only(c: &mut Criterion) {
+    let structured = Logger::root(JournaldDrain.ignore_res(), o!("service" => "bench"));
+    let message_only = Logger::root(
+        JournaldDrain::builder()
+            .message_only(true)
+            .build()
+            .unwrap()
+            .ignore_res(),
+        o!("service" => "bench"),
+    );
+
+    let mut group = c.benchmark_group("message_only");
+    group.bench_function("structured", |b| {
+        b.iter(|| info!(structured, "benchmark"; "count" => 42, "name" => "value"))
+    });
+    group.bench_function("message_only", |b| {
+        b.iter(|| info!(message_only, "benchmark"; "count" => 42, "name" => "value"))
+    });
+    group.finish();
+}
+
+/// Serialization only, with and without preallocating the fields of a 20-field entry
+fn bench_expected_fields(c: &mut Criterion) {
+    let logger = |expected_fields| {
+        let drain = JournaldDrain::builder()
+            .dry_run(true)
+            .expected_fields(expected_fields)
+            .build()
+            .unwrap();
+        Logger::root(drain.ignore_res(), o!("service" => "bench", "version" => 2))
+    };
+    let default = logger(0);
+    let hinted = logger(20);
+
+    macro_rules! log_20_fields {
+        ($logger:expr) => {
+            info!($logger, "benchmark";
+                "f1" => 1, "f2" => 2, "f3" => 3, "f4" => 4, "f5" => 5, "f6" => 6,
+                "f7" => 7, "f8" => 8, "f9" => 9, "f10" => 10, "f11" => 11, "f12" => 12,
+                "f13" => 13, "f14" => "fourteen")
+        };
+    }
+
+    let mut group = c.benchmark_group("expected_fields");
+    group.bench_function("default", |b| b.iter(|| log_20_fields!(default)));
+    group.bench_function("hinted", |b| b.iter(|| log_20_fields!(hinted)));
+    group.finish();
+}
+
+/// Serialization only, of keys that need sanitizing and prefixing, which allocates the
+/// most per field. Compare runs with `--save-baseline` and `--baseline`.
+fn bench_sanitized_keys(c: &mut Criterion) {
+    let drain = JournaldDrain::builder()
+        .dry_run(true)
+        .context_prefix("CTX_")
+        .record_prefix("REC_")
+        .build()
+        .unwrap();
+    let logger = Logger::root(
+        drain.ignore_res(),
+        o!("service.name" => "bench", "service.version" => 2),
+    );
+
+    c.bench_function("sanitized_keys", |b| {
+        b.iter(|| {
+            info!(logger, "benchmark";
+                "request.id" => "abc", "http.status" => 200, "http.method" => "GET",
+                "user.id" => 42, "latency_ms" => 12, "bytes.sent" => 1024)
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_message_only,
+    bench_expected_fields,
+    bench_sanitized_keys
+);
+criterion_main!(benches);
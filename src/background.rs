@@ -0,0 +1,477 @@
+//! Sending entries from a background thread.
+
+use std::collections::VecDeque;
+use std::panic;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use libsystemd::errors::SdError;
+use libsystemd::logging::Priority;
+use slog::{Drain, Level, OwnedKVList, Record};
+
+use builder::{ConfigError, JournaldDrainBuilder};
+use sink::{BinaryField, Field, JournalSink};
+use JournaldDrain;
+
+/// What [`AsyncJournaldDrain`](struct.AsyncJournaldDrain.html) does with an entry when
+/// its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait in `log` until the background thread made room
+    Block,
+    /// Drop the oldest queued entry to make room
+    DropOldest,
+    /// Drop the new entry
+    DropNew,
+}
+
+/// A `JournaldDrain` sending the entries from a background thread, so `log` doesn't
+/// block on journald.
+///
+/// Records are serialized in `log` on the calling thread, only sending the entry is
+/// queued. The queue holds at most `capacity` entries, beyond that the
+/// [`OverflowPolicy`](enum.OverflowPolicy.html) applies. Dropping the drain sends the
+/// queued entries before it returns.
+///
+/// As `log` returns before the entry is sent, it can't report failures to send. Those
+/// and dropped entries are counted instead, see [`failed`](#method.failed) and
+/// [`dropped`](#method.dropped). A sink that panics fails only the entry it panicked
+/// on, the background thread keeps sending.
+///
+/// ```
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog::Drain;
+/// use slog_journald::{AsyncJournaldDrain, JournaldDrain, OverflowPolicy};
+///
+/// fn main() {
+///     let drain =
+///         AsyncJournaldDrain::new(JournaldDrain::builder(), 1024, OverflowPolicy::DropOldest)
+///             .unwrap();
+///     let log = slog::Logger::root(drain.ignore_res(), slog::o!());
+/// }
+/// ```
+pub struct AsyncJournaldDrain {
+    drain: JournaldDrain,
+    queue: Arc<Queue>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AsyncJournaldDrain {
+    /// Build the drain configured by `builder`, queueing up to `capacity` entries.
+    ///
+    /// Fails if `capacity` is `0`.
+    pub fn new(
+        builder: JournaldDrainBuilder,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Result<AsyncJournaldDrain, ConfigError> {
+        if capacity == 0 {
+            return Err(ConfigError::new("queue capacity of 0 entries".to_string()));
+        }
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                entries: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            policy,
+            dropped: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        });
+        let drain = builder
+            .wrap_sinks(|sink| {
+                Arc::new(Enqueue {
+                    queue: queue.clone(),
+                    sink,
+                })
+            })
+            .build()?;
+        let worker = {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name("slog-journald".to_string())
+                .spawn(move || queue.run())
+                .map_err(|e| ConfigError::new(format!("can't start sending thread: {}", e)))?
+        };
+        Ok(AsyncJournaldDrain {
+            drain,
+            queue,
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+
+    /// Number of entries dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of entries the background thread failed to send, including those its sink
+    /// panicked on.
+    pub fn failed(&self) -> u64 {
+        self.queue.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drain for AsyncJournaldDrain {
+    type Ok = ();
+    type Err = ::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), ::Error> {
+        self.drain.log(record, values)
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.drain.is_enabled(level)
+    }
+}
+
+impl Drop for AsyncJournaldDrain {
+    fn drop(&mut self) {
+        self.queue.lock().closed = true;
+        self.queue.not_empty.notify_one();
+        let worker = match self.worker.get_mut() {
+            Ok(worker) => worker.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(worker) = worker {
+            // The worker only panics if a sink does, there's nothing left to send then
+            let _ = worker.join();
+        }
+    }
+}
+
+/// An entry waiting to be sent to `sink`
+struct Queued {
+    sink: Arc<dyn JournalSink>,
+    priority: Priority,
+    message: String,
+    fields: Vec<Field>,
+    binary: Vec<BinaryField>,
+}
+
+struct Queue {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+struct State {
+    entries: VecDeque<Queued>,
+    /// The drain was dropped, send the remaining entries and stop
+    closed: bool,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn push(&self, entry: Queued) {
+        let mut state = self.lock();
+        if state.closed {
+            // The background thread is gone, nobody would send the entry
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if state.entries.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Block => {
+                    while state.entries.len() >= self.capacity && !state.closed {
+                        state = match self.not_full.wait(state) {
+                            Ok(state) => state,
+                            Err(poisoned) => poisoned.into_inner(),
+                        };
+                    }
+                    if state.closed {
+                        // Stopped while we waited, the entry would never leave the queue
+                        self.failed.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    state.entries.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNew => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+        state.entries.push_back(entry);
+        self.not_empty.notify_one();
+    }
+
+    /// Send entries until the drain is dropped and the queue is empty
+    fn run(&self) {
+        let _closed = CloseOnExit(self);
+        loop {
+            let entry = {
+                let mut state = self.lock();
+                loop {
+                    if let Some(entry) = state.entries.pop_front() {
+                        break entry;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = match self.not_empty.wait(state) {
+                        Ok(state) => state,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                }
+            };
+            self.not_full.notify_one();
+            let sent = panic::catch_unwind(|| {
+                if entry.binary.is_empty() {
+                    entry
+                        .sink
+                        .send(entry.priority, &entry.message, &entry.fields)
+                } else {
+                    entry.sink.send_binary(
+                        entry.priority,
+                        &entry.message,
+                        &entry.fields,
+                        &entry.binary,
+                    )
+                }
+            });
+            if !matches!(sent, Ok(Ok(()))) {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Marks the queue closed when the background thread stops, even by a panic, so `push`
+/// doesn't wait for it
+struct CloseOnExit<'a>(&'a Queue);
+
+impl<'a> Drop for CloseOnExit<'a> {
+    fn drop(&mut self) {
+        self.0.lock().closed = true;
+        self.0.not_full.notify_all();
+    }
+}
+
+/// Sink queueing the entries for `sink`
+struct Enqueue {
+    queue: Arc<Queue>,
+    sink: Arc<dyn JournalSink>,
+}
+
+impl JournalSink for Enqueue {
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+        self.send_binary(priority, message, fields, &[])
+    }
+
+    fn send_binary(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[Field],
+        binary: &[BinaryField],
+    ) -> Result<(), SdError> {
+        self.queue.push(Queued {
+            sink: self.sink.clone(),
+            priority,
+            message: message.to_string(),
+            fields: fields.to_vec(),
+            binary: binary.to_vec(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Level;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::time::Duration;
+
+    type Sent = Arc<Mutex<Vec<String>>>;
+
+    /// Sink that signals each entry it got and waits for `gate` before keeping it
+    struct GatedSink {
+        entered: Mutex<Sender<()>>,
+        gate: Mutex<Receiver<()>>,
+        sent: Sent,
+    }
+
+    impl JournalSink for GatedSink {
+        fn send(&self, _: Priority, message: &str, _: &[Field]) -> Result<(), SdError> {
+            let _ = self.entered.lock().unwrap().send(());
+            // Fails once the test drops the sender, letting all entries through
+            let _ = self.gate.lock().unwrap().recv();
+            self.sent.lock().unwrap().push(message.to_string());
+            Ok(())
+        }
+    }
+
+    fn log(drain: &AsyncJournaldDrain, msg: &str) {
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+    }
+
+    /// Messages sent with `policy` when 4 entries are logged while the sink is busy
+    /// with the first and the queue holds 2
+    fn overflow(policy: OverflowPolicy) -> (Vec<String>, u64) {
+        let (entered, entered_rx) = mpsc::channel();
+        let (gate, gate_rx) = mpsc::channel();
+        let sent = Sent::default();
+        let sink = GatedSink {
+            entered: Mutex::new(entered),
+            gate: Mutex::new(gate_rx),
+            sent: sent.clone(),
+        };
+        let drain =
+            AsyncJournaldDrain::new(JournaldDrain::builder().sink(sink), 2, policy).unwrap();
+        log(&drain, "1");
+        entered_rx.recv().unwrap();
+        for msg in &["2", "3", "4"] {
+            log(&drain, msg);
+        }
+        let dropped = drain.dropped();
+        drop(gate);
+        drop(drain);
+        let sent = sent.lock().unwrap().clone();
+        (sent, dropped)
+    }
+
+    #[test]
+    fn overflow_policies() {
+        assert_eq!(
+            overflow(OverflowPolicy::DropNew),
+            (vec!["1".into(), "2".into(), "3".into()], 1)
+        );
+        assert_eq!(
+            overflow(OverflowPolicy::DropOldest),
+            (vec!["1".into(), "3".into(), "4".into()], 1)
+        );
+    }
+
+    #[test]
+    fn blocks_and_flushes_on_drop() {
+        let (entered, _entered_rx) = mpsc::channel();
+        let (gate, gate_rx) = mpsc::channel::<()>();
+        drop(gate);
+        let sent = Sent::default();
+        let sink = GatedSink {
+            entered: Mutex::new(entered),
+            gate: Mutex::new(gate_rx),
+            sent: sent.clone(),
+        };
+        let drain = AsyncJournaldDrain::new(
+            JournaldDrain::builder().sink(sink),
+            1,
+            OverflowPolicy::Block,
+        )
+        .unwrap();
+        for i in 0..100 {
+            log(&drain, &i.to_string());
+        }
+        assert_eq!(drain.dropped(), 0);
+        drop(drain);
+        let expected: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        assert_eq!(*sent.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn survives_panicking_sink() {
+        struct Panicking;
+
+        impl JournalSink for Panicking {
+            fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+                panic!("sink failed");
+            }
+        }
+
+        let drain = AsyncJournaldDrain::new(
+            JournaldDrain::builder().sink(Panicking),
+            1,
+            OverflowPolicy::Block,
+        )
+        .unwrap();
+        for i in 0..5 {
+            log(&drain, &i.to_string());
+        }
+        let queue = drain.queue.clone();
+        drop(drain);
+        assert_eq!(queue.failed.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn push_after_worker_stopped() {
+        let drain =
+            AsyncJournaldDrain::new(JournaldDrain::builder(), 1, OverflowPolicy::Block).unwrap();
+        // As if the background thread had stopped unexpectedly
+        drain.queue.lock().closed = true;
+        drain.worker.lock().unwrap().take().unwrap().join().unwrap();
+        for i in 0..3 {
+            log(&drain, &i.to_string());
+        }
+        assert_eq!(drain.failed(), 3);
+    }
+
+    #[test]
+    fn blocked_push_after_worker_stopped() {
+        let (entered, entered_rx) = mpsc::channel();
+        let (gate, gate_rx) = mpsc::channel::<()>();
+        let sent = Sent::default();
+        let sink = GatedSink {
+            entered: Mutex::new(entered),
+            gate: Mutex::new(gate_rx),
+            sent: sent.clone(),
+        };
+        let drain = AsyncJournaldDrain::new(
+            JournaldDrain::builder().sink(sink),
+            1,
+            OverflowPolicy::Block,
+        )
+        .unwrap();
+        log(&drain, "1");
+        entered_rx.recv().unwrap();
+        log(&drain, "2");
+        thread::scope(|scope| {
+            let blocked = scope.spawn(|| log(&drain, "3"));
+            // Give the push time to wait for room in the full queue
+            thread::sleep(Duration::from_millis(50));
+            // As if the background thread had stopped unexpectedly
+            drain.queue.lock().closed = true;
+            drain.queue.not_full.notify_all();
+            blocked.join().unwrap();
+        });
+        let failed = drain.failed();
+        drop(gate);
+        drop(drain);
+        assert_eq!(failed, 1);
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec!["1".to_string(), "2".to_string()]
+        );
+    }
+
+    #[test]
+    fn zero_capacity() {
+        assert!(
+            AsyncJournaldDrain::new(JournaldDrain::builder(), 0, OverflowPolicy::Block).is_err()
+        );
+    }
+}
@@ -0,0 +1,1473 @@
+//! Configuration of a [`JournaldDrain`](../struct.JournaldDrain.html).
+
+use std::borrow::Cow;
+#[cfg(feature = "nested-values")]
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::panic::RefUnwindSafe;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clock::{Clock, SystemClock};
+use libsystemd::logging::Priority;
+#[cfg(feature = "nested-values")]
+use nested::SerdeFormatter;
+use rate_limit::RateLimitEstimator;
+use sampling::Sampler;
+use sanitizer::KeySanitizer;
+use sink::{self, Field, JournalSink, JournaldSink, NamespaceSink};
+use slog::{Level, Record};
+use well_known::WellKnownFields;
+use {level_to_priority, JournaldDrain, SOURCE_FIELDS};
+
+/// Default limit of [`JournaldDrainBuilder::max_field_len`](struct.JournaldDrainBuilder.html#method.max_field_len)
+/// and [`max_message_len`](struct.JournaldDrainBuilder.html#method.max_message_len), 1 MiB.
+///
+/// Journald accepts much larger entries, but in practice values this large are runaway
+/// dumps.
+pub const DEFAULT_MAX_LEN: usize = 1024 * 1024;
+
+/// Builder for a configured [`JournaldDrain`](struct.JournaldDrain.html).
+///
+/// The defaults match the behavior of the plain `JournaldDrain` constant.
+///
+/// # Examples
+/// ```
+/// use slog_journald::JournaldDrainBuilder;
+///
+/// let drain = JournaldDrainBuilder::new()
+///     .context_prefix("CTX_")
+///     .record_prefix("REC_")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct JournaldDrainBuilder {
+    config: Config,
+}
+
+impl JournaldDrainBuilder {
+    /// Create a builder with the default configuration.
+    pub fn new() -> JournaldDrainBuilder {
+        JournaldDrainBuilder::default()
+    }
+
+    /// Prefix prepended to the sanitized keys of the logger's context values
+    /// (the ones added with `o!(...)`).
+    ///
+    /// The prefix must itself be the start of a valid journald field name, e.g. `CTX_`.
+    /// Defaults to no prefix.
+    pub fn context_prefix<S: Into<String>>(mut self, prefix: S) -> JournaldDrainBuilder {
+        self.config.context_prefix = prefix.into();
+        self
+    }
+
+    /// Prefix prepended to the sanitized keys of the values passed with the
+    /// individual logging call.
+    ///
+    /// The prefix must itself be the start of a valid journald field name, e.g. `REC_`.
+    /// Defaults to no prefix.
+    pub fn record_prefix<S: Into<String>>(mut self, prefix: S) -> JournaldDrainBuilder {
+        self.config.record_prefix = prefix.into();
+        self
+    }
+
+    /// Prefix prepended to the sanitized keys of all logged values, both context and
+    /// record values. Shorthand for setting [`context_prefix`](#method.context_prefix)
+    /// and [`record_prefix`](#method.record_prefix) to the same prefix.
+    ///
+    /// With a prefix like `APP_`, keys such as `message` or `priority` can't clash with
+    /// journald's own fields. The drain's fields, e.g. `CODE_FILE`, aren't prefixed.
+    pub fn key_prefix<S: Into<String>>(self, prefix: S) -> JournaldDrainBuilder {
+        let prefix = prefix.into();
+        self.context_prefix(prefix.clone()).record_prefix(prefix)
+    }
+
+    /// Only send the message and priority of each record.
+    ///
+    /// This skips the `CODE_*` fields, the fields added to the drain like with
+    /// [`add_field`](#method.add_field) as well as all context and record values,
+    /// which is the cheapest way to log when structured data isn't needed.
+    /// Defaults to `false`.
+    pub fn message_only(mut self, message_only: bool) -> JournaldDrainBuilder {
+        self.config.message_only = message_only;
+        self
+    }
+
+    /// Key of the logged value that is sent as journald's `MESSAGE_ID`.
+    ///
+    /// The value has to be a 128-bit id like the ones generated by `systemd-id128 new`,
+    /// logging a malformed id fails with a serialization error. Together with
+    /// [`skip_failed_values`](#method.skip_failed_values) the entry is sent without it.
+    /// Other keys, including `message_id` when another key is configured, are renamed to
+    /// `MESSAGE_ID_` by [`protect_source_fields`](#method.protect_source_fields).
+    /// Defaults to `message_id`.
+    pub fn message_id_key<S: Into<String>>(mut self, key: S) -> JournaldDrainBuilder {
+        self.config.message_id_key = key.into();
+        self
+    }
+
+    /// Key of the logged value that is sent as `SOURCE_REALTIME_TIMESTAMP`, the time the
+    /// event happened in microseconds since the Unix epoch.
+    ///
+    /// Use it for events logged later than they happened, e.g. when processing a backlog.
+    /// Journald keeps stamping entries with the time it received them and stores this
+    /// as a field next to it. [`RemoteSink`](struct.RemoteSink.html) sends it as the
+    /// entry's `__REALTIME_TIMESTAMP`, which `systemd-journal-remote` stores the entry
+    /// with. Logging anything but an unsigned integer for this key fails with a
+    /// serialization error. Records without the key get no such field. Defaults to no
+    /// key.
+    pub fn source_timestamp_key<S: Into<String>>(mut self, key: S) -> JournaldDrainBuilder {
+        self.config.source_timestamp_key = Some(key.into());
+        self
+    }
+
+    /// Add a `<NAME>_UNIT` field to numeric values whose field name ends in a unit suffix.
+    ///
+    /// E.g. `"latency_ms" => 42` is sent as `LATENCY_MS=42` and `LATENCY_MS_UNIT=milliseconds`.
+    /// Recognized suffixes are `_NS`, `_US`, `_MS`, `_SECS`, `_BYTES` and `_PCT`.
+    /// Defaults to `false`.
+    pub fn unit_fields(mut self, unit_fields: bool) -> JournaldDrainBuilder {
+        self.config.unit_fields = unit_fields;
+        self
+    }
+
+    /// Send the entries to `sink` instead of the local journald.
+    pub fn sink<S: JournalSink + 'static>(mut self, sink: S) -> JournaldDrainBuilder {
+        self.config.sink = Arc::new(sink);
+        self
+    }
+
+    /// Take the time for timestamp fields and the rate limit estimate from `clock` instead
+    /// of the system clocks.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> JournaldDrainBuilder {
+        self.config.clock = Arc::new(clock);
+        self
+    }
+
+    /// Estimate how many entries journald drops due to its rate limiting.
+    ///
+    /// Journald doesn't tell the sender when it suppresses entries, so the drain mirrors
+    /// the limit configured for the service (`RateLimitIntervalSec=` and `RateLimitBurst=`,
+    /// 30 seconds and 10000 entries by default) and counts every entry sent beyond `burst`
+    /// within `interval`. The count is available from
+    /// [`JournaldDrain::rate_limited_estimate`](struct.JournaldDrain.html#method.rate_limited_estimate).
+    ///
+    /// This is only an estimate: journald scales the burst with the available disk space
+    /// and shares the limit between all processes of a service.
+    pub fn estimate_rate_limit(mut self, interval: Duration, burst: u64) -> JournaldDrainBuilder {
+        self.config.rate_limit = Some(RateLimitEstimator::new(interval, burst));
+        self
+    }
+
+    /// How the record's message is sent, see [`MessageMode`](enum.MessageMode.html).
+    ///
+    /// Defaults to `MessageMode::Full`.
+    pub fn message_mode(mut self, mode: MessageMode) -> JournaldDrainBuilder {
+        self.config.message_mode = mode;
+        self
+    }
+
+    /// Add a `MONOTONIC_NS` field with the nanoseconds since the drain was built.
+    ///
+    /// Unlike journald's wall-clock timestamps this isn't affected by clock adjustments,
+    /// so it's well suited for measuring the time between two entries of the same process.
+    /// Defaults to `false`.
+    pub fn monotonic_time(mut self, monotonic_time: bool) -> JournaldDrainBuilder {
+        self.config.monotonic_time = monotonic_time;
+        self
+    }
+
+    /// Format values of the serde type `type_name` with `formatter`.
+    ///
+    /// This applies to nested values logged with `slog::Serde`, the type name is the
+    /// one serde sees, i.e. the name of the struct or enum. The formatter gets the value
+    /// converted to JSON and returns the field value.
+    #[cfg(feature = "nested-values")]
+    pub fn serde_formatter<S, F>(mut self, type_name: S, formatter: F) -> JournaldDrainBuilder
+    where
+        S: Into<String>,
+        F: Fn(&serde_json::Value) -> String + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config
+            .serde_formatters
+            .insert(type_name.into(), Box::new(formatter));
+        self
+    }
+
+    /// Flatten nested sequences and tuples into one field per element, named after the
+    /// element's index, instead of sending them as a single JSON array.
+    ///
+    /// E.g. `tags: ["a", "b"]` in a value logged under `ctx` becomes `CTX_TAGS_0=a` and
+    /// `CTX_TAGS_1=b`, which journald can match on one by one. Empty sequences aren't
+    /// sent at all. With `false` the example is sent as `CTX_TAGS=["a","b"]`, which keeps
+    /// the number of fields down for long sequences. Defaults to `true`.
+    #[cfg(feature = "nested-values")]
+    pub fn indexed_sequences(mut self, indexed_sequences: bool) -> JournaldDrainBuilder {
+        self.config.indexed_sequences = indexed_sequences;
+        self
+    }
+
+    /// Truncate field values longer than `max_bytes`, ending them in the truncation marker.
+    ///
+    /// Journald rejects entries that are too large, this keeps a single huge value
+    /// from losing the whole entry. Entries with a truncated field or message get a
+    /// `TRUNCATED=1` field. A logged error whose message with all its sources was
+    /// truncated also gets an `ERROR_TRUNCATED=1` field, see
+    /// [`error_field_prefix`](#method.error_field_prefix). Defaults to
+    /// [`DEFAULT_MAX_LEN`](constant.DEFAULT_MAX_LEN.html), use `usize::MAX` for no limit.
+    pub fn max_field_len(mut self, max_bytes: usize) -> JournaldDrainBuilder {
+        self.config.max_field_len = Some(max_bytes);
+        self
+    }
+
+    /// Truncate messages longer than `max_bytes`, ending them in the truncation marker.
+    ///
+    /// Entries with a truncated message get a `TRUNCATED=1` field. Defaults to
+    /// [`DEFAULT_MAX_LEN`](constant.DEFAULT_MAX_LEN.html), use `usize::MAX` for no limit.
+    pub fn max_message_len(mut self, max_bytes: usize) -> JournaldDrainBuilder {
+        self.config.max_message_len = Some(max_bytes);
+        self
+    }
+
+    /// Text ending every value the drain truncates, e.g. for log parsers looking for it.
+    ///
+    /// The marker counts towards the length limit. A `{}` in the marker is replaced with
+    /// the number of bytes cut off. If the limit leaves no room for the marker, the
+    /// marker is cut off at the limit as well. Defaults to `…[truncated {} bytes]`.
+    pub fn truncation_marker<S: Into<String>>(mut self, marker: S) -> JournaldDrainBuilder {
+        self.config.truncation_marker = marker.into();
+        self
+    }
+
+    /// Add a `CRATE` field with the crate the logging call is in.
+    ///
+    /// This is the first segment of `CODE_MODULE`, so a drain shared by many crates
+    /// can be filtered by crate without parsing the module path. Defaults to `false`.
+    pub fn crate_field(mut self, crate_field: bool) -> JournaldDrainBuilder {
+        self.config.crate_field = crate_field;
+        self
+    }
+
+    /// Merge values logged under the same field name into one field, joined by `separator`.
+    ///
+    /// Journald keeps every value of a field that appears several times in an entry,
+    /// which is what the drain does by default. This is for consumers that can't handle
+    /// repeated fields.
+    pub fn merge_repeated_keys<S: Into<String>>(mut self, separator: S) -> JournaldDrainBuilder {
+        self.config.merge_separator = Some(separator.into());
+        self
+    }
+
+    /// Serialize the records as usual, but don't send them.
+    ///
+    /// This catches serialization errors without writing to journald.
+    /// Defaults to `false`.
+    pub fn dry_run(mut self, dry_run: bool) -> JournaldDrainBuilder {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Call `callback` with the priority, message and fields each entry would be sent with
+    /// in [`dry_run`](#method.dry_run) mode.
+    pub fn on_dry_run<F>(mut self, callback: F) -> JournaldDrainBuilder
+    where
+        F: Fn(Priority, &str, &[Field]) + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config.on_dry_run = Some(Box::new(callback));
+        self
+    }
+
+    /// Send entries of `priority` to `sink` instead of the default sink.
+    ///
+    /// E.g. to route critical entries elsewhere, or to capture only errors in tests.
+    pub fn priority_sink<S: JournalSink + 'static>(
+        mut self,
+        priority: Priority,
+        sink: S,
+    ) -> JournaldDrainBuilder {
+        self.config.priority_sinks[u8::from(priority) as usize] = Some(Arc::new(sink));
+        self
+    }
+
+    /// Rename user fields that would end up with the name of a field journald treats
+    /// specially, like `MESSAGE`, `PRIORITY` or `CODE_FILE`, by appending an underscore.
+    ///
+    /// Fields with a leading underscore, like `_PID` or `__CURSOR`, are trusted or
+    /// address fields set by journald itself. Keys can't be sanitized into those, so
+    /// this only has to guard the special fields without leading underscore.
+    ///
+    /// The names are compared exactly, there's no case-insensitive matching to configure:
+    /// every name sent has passed the check for valid field names, so it's uppercase like
+    /// journald's own fields. Defaults to `false`.
+    pub fn protect_reserved_fields(mut self, protect: bool) -> JournaldDrainBuilder {
+        self.config.protect_reserved_fields = protect;
+        self
+    }
+
+    /// Send values whose key starts with `object.` as journald's `OBJECT_*` fields.
+    ///
+    /// E.g. `"object.uid" => 1000` becomes `OBJECT_UID=1000`, regardless of the key prefixes.
+    /// These fields describe the subject of an action in audit logs, as opposed to the
+    /// logging process. Only `object.pid` is sent as `OBJECT_PID_`, as journald looks up
+    /// the process with the pid in `OBJECT_PID`, which has to be set with
+    /// [`WellKnownFields::object_pid`](struct.WellKnownFields.html#method.object_pid)
+    /// to be validated. Defaults to `false`.
+    pub fn object_fields(mut self, object_fields: bool) -> JournaldDrainBuilder {
+        self.config.object_fields = object_fields;
+        self
+    }
+
+    /// Add `STARTUP=1` to the first entry the drain sends.
+    ///
+    /// This marks where each run of the process begins in the journal, e.g. for
+    /// `journalctl STARTUP=1`. Defaults to `false`.
+    pub fn startup_field(mut self, startup_field: bool) -> JournaldDrainBuilder {
+        self.config.startup_field = startup_field;
+        self
+    }
+
+    /// Map slog's `Info` level to journald's `Notice` priority.
+    ///
+    /// By default the levels map one step below their name: `Info` is sent as `Notice`,
+    /// `Debug` as `Info` and `Trace` as `Debug`, so `journalctl -p info` misses debug
+    /// but includes info logs. With `false`, `Info` is sent as `Info` and both `Debug`
+    /// and `Trace` as `Debug`. Defaults to `true`.
+    pub fn info_as_notice(mut self, info_as_notice: bool) -> JournaldDrainBuilder {
+        self.config.info_as_notice = info_as_notice;
+        self
+    }
+
+    /// Send only every `one_in`th entry of `priority`.
+    ///
+    /// The next entry of `priority` that is sent carries the number of entries dropped
+    /// since the previous one in a `SUPPRESSED_SINCE_LAST` field, so the volume stays
+    /// visible in the journal. Entries picked by sampling that fail to serialize or send
+    /// are counted there as well. `build` fails if `one_in` is `0`.
+    pub fn sample(mut self, priority: Priority, one_in: u64) -> JournaldDrainBuilder {
+        self.config.samplers[u8::from(priority) as usize] = Some(Sampler::new(one_in));
+        self
+    }
+
+    /// Add a `<NAME>_TYPE` companion with the Rust type of every integer field, e.g.
+    /// `COUNT_TYPE=u64` for a `u64` logged as `count`.
+    ///
+    /// This keeps the sign and width for consumers reconstructing typed values, at the
+    /// cost of a second field per integer, which counts towards journald's per-entry
+    /// field limit and storage. Defaults to `false`.
+    pub fn integer_type_fields(mut self, integer_type_fields: bool) -> JournaldDrainBuilder {
+        self.config.integer_type_fields = integer_type_fields;
+        self
+    }
+
+    /// Trim leading and trailing whitespace from string values.
+    ///
+    /// Journald matches field values exactly, so `journalctl USER=alice` doesn't find
+    /// `USER=alice ` logged by accident. Applies to `&str` and format arguments values,
+    /// not to the message. Defaults to `false`.
+    pub fn trim_values(mut self, trim_values: bool) -> JournaldDrainBuilder {
+        self.config.trim_values = trim_values;
+        self
+    }
+
+    /// Add an `ELAPSED_MS` field with the milliseconds since the drain was built.
+    ///
+    /// Building the drain is usually one of the first things a process does, so this
+    /// shows the progression of a run in plain `journalctl` output without comparing
+    /// timestamps. For precise measurements see
+    /// [`monotonic_time`](#method.monotonic_time). Defaults to `false`.
+    pub fn elapsed_field(mut self, elapsed_field: bool) -> JournaldDrainBuilder {
+        self.config.elapsed_field = elapsed_field;
+        self
+    }
+
+    /// Add an `APP_INVOCATION_ID` field with the `INVOCATION_ID` systemd set for the
+    /// service, to correlate all entries of one run.
+    ///
+    /// Journald already adds the trusted `_SYSTEMD_INVOCATION_ID` field, but only for
+    /// processes it can attribute to the unit. The environment variable is read once
+    /// when the drain is built, without it no field is added. Defaults to `false`.
+    pub fn invocation_id_field(mut self, invocation_id_field: bool) -> JournaldDrainBuilder {
+        self.config.invocation_id_field = invocation_id_field;
+        self
+    }
+
+    /// Add the field `name` with `value` to every entry.
+    ///
+    /// Unlike keys logged with slog, `name` isn't sanitized, it has to be a valid journald
+    /// field name already: uppercase ASCII letters, digits and underscores, starting with
+    /// a letter and at most 64 characters long. `build` fails otherwise. E.g. to set the
+    /// container shown with the entries:
+    ///
+    /// ```
+    /// let drain = slog_journald::JournaldDrain::builder()
+    ///     .add_field("CONTAINER_NAME", "web-1")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// `build` also fails for the fields the drain sets itself, `MESSAGE`, `PRIORITY`,
+    /// `MESSAGE_ID` and the `CODE_*` fields, and for the fields of
+    /// [`WellKnownFields`](struct.WellKnownFields.html), like `SYSLOG_IDENTIFIER`, whose
+    /// values journald expects in a certain form. Set those with
+    /// [`well_known_fields`](#method.well_known_fields) or
+    /// [`syslog_identifier`](#method.syslog_identifier) instead.
+    pub fn add_field<K, V>(mut self, name: K, value: V) -> JournaldDrainBuilder
+    where
+        K: Into<Cow<'static, str>>,
+        V: Into<String>,
+    {
+        self.config.static_fields.push((name.into(), value.into()));
+        self
+    }
+
+    /// Debugging aid: also write every entry to stderr as raw bytes of journald's native
+    /// protocol, exactly as the drain hands them to journald.
+    ///
+    /// Unlike human-readable output this shows the framing of values with newlines, so
+    /// it's meant for debugging the drain itself, not for production. Defaults to `false`.
+    pub fn debug_dump(mut self, debug_dump: bool) -> JournaldDrainBuilder {
+        self.config.debug_dump = debug_dump;
+        self
+    }
+
+    /// Rename a field whose name is already used in the entry by appending `_2`, `_3`
+    /// and so on, up to `_<max_suffix>`.
+    ///
+    /// Different keys can end up with the same field name after sanitizing, e.g.
+    /// `user.id` and `user_id`. Journald would keep both values under one name, hiding
+    /// which is which. Once all suffixes up to `max_suffix` are taken, or the suffixed name
+    /// would be longer than journald accepts, further fields with that name are dropped
+    /// and counted in a `COLLISIONS_DROPPED` field instead, so a pathological record can't
+    /// grow the entry without bounds. Values merged with
+    /// [`merge_repeated_keys`](#method.merge_repeated_keys) aren't renamed.
+    pub fn disambiguate_collisions(mut self, max_suffix: usize) -> JournaldDrainBuilder {
+        self.config.max_collision_suffix = Some(max_suffix);
+        self
+    }
+
+    /// Add an `APP_CMDLINE` field with the arguments of the process, joined by spaces.
+    ///
+    /// Journald's `_CMDLINE` is only available for processes it can inspect. The
+    /// arguments are read once when the drain is built, long command lines are cut to
+    /// [`max_field_len`](#method.max_field_len) like other values. Defaults to `false`.
+    pub fn cmdline_field(mut self, cmdline_field: bool) -> JournaldDrainBuilder {
+        self.config.cmdline_field = cmdline_field;
+        self
+    }
+
+    /// Treat the fields of [`add_field`](#method.add_field) as defaults, only sent if
+    /// the record and logger values don't have a field with the same name.
+    ///
+    /// E.g. with `add_field("COMPONENT", "core")`, a record logging `"component" => "db"`
+    /// is sent with `COMPONENT=db` only, instead of both values. Defaults to `false`.
+    pub fn static_fields_as_defaults(mut self, as_defaults: bool) -> JournaldDrainBuilder {
+        self.config.static_fields_as_defaults = as_defaults;
+        self
+    }
+
+    /// Also send the value logged under `key` as `EXEMPLAR_TRACE_ID`, formatted like the
+    /// `trace_id` of Prometheus exemplars: 32 lowercase hex digits.
+    ///
+    /// This allows jumping from a metric exemplar to the entries of the same trace. 64-bit
+    /// ids are padded to 128 bits with leading zeros, like OpenTelemetry does. Values that
+    /// aren't a hex trace id are only sent under their own field. Defaults to none.
+    pub fn exemplar_trace_id_key<S: Into<String>>(mut self, key: S) -> JournaldDrainBuilder {
+        self.config.exemplar_trace_id_key = Some(key.into());
+        self
+    }
+
+    /// Catch panics of `Display` implementations while formatting the message or values,
+    /// sending `<format panicked>` in their place.
+    ///
+    /// Without this a buggy value makes the logging call panic. The panic is still
+    /// reported by the panic hook, which prints it to stderr by default. Defaults to
+    /// `false`.
+    pub fn catch_format_panics(mut self, catch_format_panics: bool) -> JournaldDrainBuilder {
+        self.config.catch_format_panics = catch_format_panics;
+        self
+    }
+
+    /// Add a `CONTENT_HASH` field with a hash of the message and fields of the entry.
+    ///
+    /// Entries with the same message and field values get the same hash, regardless of
+    /// the order of their fields, so aggregators can collapse duplicates. Fields that
+    /// differ between otherwise equal entries, like `MONOTONIC_NS`, `ELAPSED_MS` or
+    /// `STARTUP`, aren't part of the hash. The hash is 64-bit FNV-1a as 16 hex digits, it's
+    /// stable between runs but not meant to be tamper-proof. Defaults to `false`.
+    pub fn content_hash(mut self, content_hash: bool) -> JournaldDrainBuilder {
+        self.config.content_hash = content_hash;
+        self
+    }
+
+    /// Prefix of the fields the `log_error_sources` feature adds for logged errors.
+    ///
+    /// The sources of an error are sent as `<PREFIX>_SOURCE_0`, `<PREFIX>_SOURCE_1` and
+    /// so on, with their count in `<PREFIX>_SOURCE_DEPTH`. An error message truncated
+    /// to the [`max_field_len`](#method.max_field_len) is marked with
+    /// `<PREFIX>_TRUNCATED=1`, regardless of the feature. Choose a prefix like
+    /// `SLOG_ERROR` if records log their own `error_source_*` keys, so the drain's fields
+    /// don't mix with them. `build` fails if `prefix` is empty or not a valid field name
+    /// prefix. Defaults to `ERROR`.
+    pub fn error_field_prefix<S: Into<String>>(mut self, prefix: S) -> JournaldDrainBuilder {
+        self.config.error_field_prefix = prefix.into();
+        self
+    }
+
+    /// Send an entry with `DRAIN_INIT=1` when the drain is built.
+    ///
+    /// This confirms in the journal that logging is set up, with the version of this crate
+    /// in `DRAIN_VERSION` and the message mode and field length limit in `DRAIN_*` fields.
+    /// `build` fails if the entry can't be sent, e.g. because journald isn't running.
+    /// Defaults to `false`.
+    pub fn init_entry(mut self, init_entry: bool) -> JournaldDrainBuilder {
+        self.config.init_entry = init_entry;
+        self
+    }
+
+    /// Sanitize `.` in keys to `__` instead of `_`, e.g. `http.request.method` to
+    /// `HTTP__REQUEST__METHOD`.
+    ///
+    /// Other invalid characters still become a single `_`, so consumers can turn the
+    /// field names of dotted keys back into the original hierarchy, as long as the keys
+    /// don't contain `__` themselves. Defaults to `false`.
+    pub fn dots_as_double_underscore(mut self, enabled: bool) -> JournaldDrainBuilder {
+        self.config.dots_as_double_underscore = enabled;
+        self
+    }
+
+    /// Call `callback` with the time it took to serialize and send each entry.
+    ///
+    /// This helps finding expensive logging calls or journald applying backpressure. The
+    /// durations are measured with the drain's [`clock`](#method.clock), which isn't read
+    /// at all without a callback. Entries that aren't sent, e.g. in
+    /// [`dry_run`](#method.dry_run) mode, aren't reported.
+    pub fn on_timing<F>(mut self, callback: F) -> JournaldDrainBuilder
+    where
+        F: Fn(EntryTiming) + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config.on_timing = Some(Box::new(callback));
+        self
+    }
+
+    /// Retry sending an entry up to `max_retries` times when journald is temporarily
+    /// unable to accept it.
+    ///
+    /// Only failures with `EAGAIN`, `ENOBUFS` or `EINTR` are retried, e.g. when the socket
+    /// buffer is momentarily full. The drain waits 1 ms before the first retry and twice
+    /// as long before each further one, up to 32 ms, to give journald time to catch up.
+    /// The waiting is done with the drain's [`clock`](#method.clock). Defaults to `0`.
+    pub fn retry_sends(mut self, max_retries: u32) -> JournaldDrainBuilder {
+        self.config.max_send_retries = max_retries;
+        self
+    }
+
+    /// Add a `SEND_RETRIES` field with the number of retries an entry needed to be sent,
+    /// see [`retry_sends`](#method.retry_sends).
+    ///
+    /// Entries sent at the first attempt don't get the field, so it only shows up when
+    /// journald applies backpressure. Defaults to `false`.
+    pub fn send_retries_field(mut self, send_retries_field: bool) -> JournaldDrainBuilder {
+        self.config.send_retries_field = send_retries_field;
+        self
+    }
+
+    /// Reserve room for `fields` fields per entry up front.
+    ///
+    /// With a hint matching the typical number of fields, including the `CODE_*` fields
+    /// of the drain, serializing doesn't have to grow the field list repeatedly. Defaults
+    /// to no preallocation.
+    pub fn expected_fields(mut self, fields: usize) -> JournaldDrainBuilder {
+        self.config.expected_fields = fields;
+        self
+    }
+
+    /// Add a `PAYLOAD_JSON` field with all logger and record values as one JSON object,
+    /// in addition to the usual fields.
+    ///
+    /// The object is `{"version":1,"fields":{...}}` with the original keys in sorted order
+    /// and numbers and booleans as JSON values, so it's deterministic and can be parsed
+    /// without knowing the sanitizing rules. This duplicates every value in the entry and
+    /// serializes the values twice. Defaults to `false`.
+    pub fn payload_json(mut self, payload_json: bool) -> JournaldDrainBuilder {
+        self.config.payload_json = payload_json;
+        self
+    }
+
+    /// Replace the default and the priority sinks with the result of `wrap` for them
+    pub(crate) fn wrap_sinks<F>(mut self, wrap: F) -> JournaldDrainBuilder
+    where
+        F: Fn(Arc<dyn JournalSink>) -> Arc<dyn JournalSink>,
+    {
+        self.config.sink = wrap(self.config.sink.clone());
+        for sink in self.config.priority_sinks.iter_mut() {
+            *sink = sink.take().map(&wrap);
+        }
+        self
+    }
+
+    /// Call `callback` once for every entry the drain tried to send, after any retries,
+    /// with binary values hex-encoded
+    pub(crate) fn on_send<F>(mut self, callback: F) -> JournaldDrainBuilder
+    where
+        F: Fn(Priority, &str, &[Field]) + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config.on_send = Some(Box::new(callback));
+        self
+    }
+
+    /// Add a `<NAME>_ISO` companion in RFC 3339 format to integers whose field name ends
+    /// in `_EPOCH` or `_EPOCH_SECS`, read as seconds since the Unix epoch.
+    ///
+    /// E.g. `"created_epoch" => 1700000000` also sends
+    /// `CREATED_EPOCH_ISO=2023-11-14T22:13:20Z`, readable in `journalctl` while keeping
+    /// the numeric value for comparisons. Years outside of 0 to 9999 don't get a
+    /// companion. Defaults to `false`.
+    pub fn epoch_iso_fields(mut self, epoch_iso_fields: bool) -> JournaldDrainBuilder {
+        self.config.epoch_iso_fields = epoch_iso_fields;
+        self
+    }
+
+    /// Which fields represent the level of the record, see
+    /// [`LevelFields`](struct.LevelFields.html).
+    ///
+    /// Defaults to none, journald's `PRIORITY` is always sent.
+    pub fn level_fields(mut self, level_fields: LevelFields) -> JournaldDrainBuilder {
+        self.config.level_fields = level_fields;
+        self
+    }
+
+    /// Drop all records while `switch` is set.
+    ///
+    /// This turns off logging to journald at runtime, e.g. from a signal handler or an
+    /// admin endpoint, without rebuilding the logger. Records are dropped before they're
+    /// serialized. By default there's no kill switch.
+    pub fn kill_switch(mut self, switch: Arc<AtomicBool>) -> JournaldDrainBuilder {
+        self.config.kill_switch = Some(switch);
+        self
+    }
+
+    /// Drop records less severe than `level` before they're serialized.
+    ///
+    /// The drain reports these levels as disabled in `Drain::is_enabled`, so e.g.
+    /// `Logger::is_enabled` is `false` for them and callers can skip preparing expensive
+    /// values. Defaults to `Level::Trace`, i.e. sending all records.
+    pub fn min_level(mut self, level: Level) -> JournaldDrainBuilder {
+        self.config.min_level = level;
+        self
+    }
+
+    /// Drop the entries the [`estimate_rate_limit`](#method.estimate_rate_limit) estimate
+    /// expects journald to suppress, instead of sending them.
+    ///
+    /// This saves writing entries journald discards anyway. Without an estimate this has
+    /// no effect. Defaults to `false`.
+    pub fn drop_rate_limited(mut self, drop_rate_limited: bool) -> JournaldDrainBuilder {
+        self.config.drop_rate_limited = drop_rate_limited;
+        self
+    }
+
+    /// Call `callback` with the reason and the record whenever the drain drops a record
+    /// instead of sending it, see [`DropReason`](enum.DropReason.html).
+    ///
+    /// Failing to send an entry isn't a drop, that's reported as the drain's error.
+    pub fn on_drop<F>(mut self, callback: F) -> JournaldDrainBuilder
+    where
+        F: Fn(DropReason, &Record) + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config.on_drop = Some(Box::new(callback));
+        self
+    }
+
+    /// Map the levels of records to journald priorities with `map`.
+    ///
+    /// This replaces the default mapping and [`info_as_notice`](#method.info_as_notice).
+    /// Use [`JournaldDrain::default_priority`](struct.JournaldDrain.html#method.default_priority)
+    /// to override only some levels:
+    ///
+    /// ```
+    /// # extern crate slog;
+    /// # extern crate slog_journald;
+    /// use slog::Level;
+    /// use slog_journald::{JournaldDrain, Priority};
+    ///
+    /// # fn main() {
+    /// let drain = JournaldDrain::builder()
+    ///     .priority_map(|level| match level {
+    ///         Level::Info => Priority::Info,
+    ///         level => JournaldDrain::default_priority(level),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn priority_map<F>(mut self, map: F) -> JournaldDrainBuilder
+    where
+        F: Fn(Level) -> Priority + Send + Sync + RefUnwindSafe + 'static,
+    {
+        self.config.priority_map = Some(Box::new(map));
+        self
+    }
+
+    /// Send the fields of each entry sorted by name, after the `CODE_*` fields.
+    ///
+    /// Journald keeps the fields in the order they were sent, which depends on the
+    /// order of the logger's and record's key-values. Sorting gives a stable order
+    /// for tests and diffs of entries, at the cost of sorting each entry.
+    /// Defaults to `false`, sending the fields in the order they were logged.
+    pub fn sort_fields(mut self, sort_fields: bool) -> JournaldDrainBuilder {
+        self.config.sort_fields = sort_fields;
+        self
+    }
+
+    /// Add a `SLOG_JOURNALD_VERSION` field with the version of this crate to every entry.
+    ///
+    /// The journald fields of a record can change between versions, e.g. how keys are
+    /// sanitized, so this tells which version wrote an entry. Defaults to `false`.
+    pub fn version_field(mut self, version_field: bool) -> JournaldDrainBuilder {
+        self.config.version_field = version_field;
+        self
+    }
+
+    /// Add a `TEST_MODE=1` field to every entry if `test_mode` is `true`.
+    ///
+    /// This separates entries of test and CI runs from production ones in a shared
+    /// journal, e.g. with `journalctl TEST_MODE=1`. The condition is up to the caller:
+    ///
+    /// ```
+    /// let drain = slog_journald::JournaldDrain::builder()
+    ///     .test_mode(cfg!(test) || std::env::var_os("CI").is_some())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Defaults to `false`.
+    pub fn test_mode(mut self, test_mode: bool) -> JournaldDrainBuilder {
+        self.config.test_mode = test_mode;
+        self
+    }
+
+    /// Rename user fields that would end up with the name of a field the drain sets
+    /// itself, `MESSAGE`, `PRIORITY`, `MESSAGE_ID` and the `CODE_*` fields, by appending
+    /// an underscore. `SYSLOG_IDENTIFIER` is protected too once the drain sets one, see
+    /// [`syslog_identifier`](#method.syslog_identifier).
+    ///
+    /// Journald keeps all values of a field sent more than once, and most tools show
+    /// only one of them, so e.g. a key `code_file` would hide the real source location.
+    /// `MESSAGE_ID` only comes from the
+    /// [`message_id_key`](#method.message_id_key), whose values are checked to be ids.
+    /// [`protect_reserved_fields`](#method.protect_reserved_fields) extends this to all
+    /// fields journald treats specially. Defaults to `true`.
+    pub fn protect_source_fields(mut self, protect: bool) -> JournaldDrainBuilder {
+        self.config.protect_source_fields = protect;
+        self
+    }
+
+    /// Add journald's well-known `fields` to every entry, see
+    /// [`WellKnownFields`](struct.WellKnownFields.html).
+    ///
+    /// They're sent like fields added with [`add_field`](#method.add_field), but `build`
+    /// also fails if a value isn't valid for its field.
+    pub fn well_known_fields(mut self, fields: WellKnownFields) -> JournaldDrainBuilder {
+        self.config.well_known_fields = Some(fields);
+        self
+    }
+
+    /// Send `identifier` as `SYSLOG_IDENTIFIER` with every entry, the name `journalctl`
+    /// shows and `journalctl -t` filters by.
+    ///
+    /// A shortcut for [`WellKnownFields::syslog_identifier`](struct.WellKnownFields.html#method.syslog_identifier),
+    /// it's kept when combined with [`well_known_fields`](#method.well_known_fields)
+    /// unless those set an identifier themselves. `build` fails if `identifier` is empty
+    /// or contains whitespace. With [`protect_source_fields`](#method.protect_source_fields)
+    /// a logged `syslog_identifier` key is renamed to `SYSLOG_IDENTIFIER_`, so entries
+    /// keep the one identifier `journalctl -t` filters by.
+    ///
+    /// ```
+    /// let drain = slog_journald::JournaldDrain::builder()
+    ///     .syslog_identifier("myservice")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn syslog_identifier<S: Into<String>>(mut self, identifier: S) -> JournaldDrainBuilder {
+        self.config.syslog_identifier = Some(identifier.into());
+        self
+    }
+
+    /// Also send the function of the logging call as `CODE_FUNCTION`, the name earlier
+    /// versions of this crate used instead of journald's `CODE_FUNC`.
+    ///
+    /// This keeps queries and dashboards built on the old name working during a
+    /// migration. Defaults to `false`.
+    pub fn code_function_field(mut self, code_function_field: bool) -> JournaldDrainBuilder {
+        self.config.code_function_field = code_function_field;
+        self
+    }
+
+    /// How to handle fields of the same name in one entry, see
+    /// [`DuplicateKeyPolicy`](enum.DuplicateKeyPolicy.html).
+    ///
+    /// This applies to the fields of the record and the logger after sanitizing, so also
+    /// to different keys with the same field name, and to fields added with
+    /// [`add_field`](#method.add_field). It doesn't affect fields renamed by
+    /// [`disambiguate_collisions`](#method.disambiguate_collisions) or merged with
+    /// [`merge_repeated_keys`](#method.merge_repeated_keys). Defaults to `KeepAll`.
+    pub fn duplicate_keys(mut self, policy: DuplicateKeyPolicy) -> JournaldDrainBuilder {
+        self.config.duplicate_keys = policy;
+        self
+    }
+
+    /// Add `APP_EUID` and `APP_EGID` fields with the effective user and group id of the
+    /// process when the record is logged.
+    ///
+    /// Journald's trusted `_UID` and `_GID` fields are the real ids of the sender, these
+    /// show the privileges the process had at the time, e.g. before and after dropping
+    /// them. The ids are read for every entry. Defaults to `false`.
+    pub fn effective_id_fields(mut self, effective_id_fields: bool) -> JournaldDrainBuilder {
+        self.config.effective_id_fields = effective_id_fields;
+        self
+    }
+
+    /// Append the values of the logged `keys` to the message, like `msg [status=500]`.
+    ///
+    /// `journalctl` shows only the message by default, this makes the most important
+    /// fields readable there too. The values are still sent as fields. `keys` are the
+    /// keys as logged, e.g. `request_id`, the record's value wins over the logger's.
+    /// Keys without a value in the record or logger are left out. By default nothing
+    /// is appended.
+    pub fn message_fields<I, S>(mut self, keys: I) -> JournaldDrainBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.message_fields = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add a `TID` field with the id of the logging thread and a `THREAD_NAME` field with
+    /// its name.
+    ///
+    /// The id is the kernel's thread id, as shown by `ps -L` or `top -H`. Threads without
+    /// a name get no `THREAD_NAME` field. Defaults to `false`.
+    pub fn thread_fields(mut self, thread_fields: bool) -> JournaldDrainBuilder {
+        self.config.thread_fields = thread_fields;
+        self
+    }
+
+    /// Turn keys into field names with `sanitizer` instead of the default rules.
+    ///
+    /// This replaces [`dots_as_double_underscore`](#method.dots_as_double_underscore),
+    /// the sanitizer decides how dots are handled. Values with keys the sanitizer drops
+    /// are handled like other invalid keys, see [`invalid_keys`](#method.invalid_keys).
+    pub fn key_sanitizer<S: KeySanitizer + 'static>(
+        mut self,
+        sanitizer: S,
+    ) -> JournaldDrainBuilder {
+        self.config.key_sanitizer = Some(Arc::new(sanitizer));
+        self
+    }
+
+    /// Add `extractor` to find out the errno of logged errors and their sources, for the
+    /// `ERRNO` field.
+    ///
+    /// Requires the `log_errno` feature. Errors that are a `std::io::Error`, or with the
+    /// `log_errno_nix` feature a `nix::errno::Errno`, are recognized without an
+    /// extractor. For other errors the extractors are tried in the order they were added,
+    /// until one returns an errno, e.g. for an error type wrapping an OS error:
+    ///
+    /// ```
+    /// # struct MyError(i32);
+    /// # impl std::fmt::Debug for MyError {
+    /// #     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { Ok(()) }
+    /// # }
+    /// # impl std::fmt::Display for MyError {
+    /// #     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { Ok(()) }
+    /// # }
+    /// # impl std::error::Error for MyError {}
+    /// use slog_journald::JournaldDrain;
+    ///
+    /// let drain = JournaldDrain::builder()
+    ///     .errno_extractor(|error| error.downcast_ref::<MyError>().map(|e| e.0))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "log_errno")]
+    pub fn errno_extractor<F>(mut self, extractor: F) -> JournaldDrainBuilder
+    where
+        F: Fn(&(dyn std::error::Error + 'static)) -> Option<i32>
+            + Send
+            + Sync
+            + RefUnwindSafe
+            + 'static,
+    {
+        self.config.errno_extractors.push(Box::new(extractor));
+        self
+    }
+
+    /// Format float values with `format` instead of Rust's default formatting.
+    ///
+    /// Defaults to `FloatFormat::Display`.
+    pub fn float_format(mut self, format: FloatFormat) -> JournaldDrainBuilder {
+        self.config.float_format = format;
+        self
+    }
+
+    /// How float values that are NaN or infinite are sent, regardless of the
+    /// [`float_format`](#method.float_format).
+    ///
+    /// Defaults to `NonFiniteFloats::Display`.
+    pub fn non_finite_floats(mut self, non_finite: NonFiniteFloats) -> JournaldDrainBuilder {
+        self.config.non_finite_floats = non_finite;
+        self
+    }
+
+    /// Send the entries to the journald instance of the log namespace `namespace`, as
+    /// set with `LogNamespace=`, instead of the default journal. Read them with
+    /// `journalctl --namespace=<namespace>`.
+    ///
+    /// Like [`sink`](#method.sink), this replaces the sink, whichever is set last wins.
+    /// Entries are written to the namespace's socket directly, entries too large for a
+    /// datagram are passed in a memfd like by the default sink. `build` fails if
+    /// `namespace` isn't a valid namespace name.
+    ///
+    /// Namespaces need systemd 245 or later. If the namespace's journald isn't running,
+    /// or systemd doesn't support namespaces, sending fails with an error for `ENOENT`,
+    /// which a [`FallbackDrain`](struct.FallbackDrain.html) treats as journald being
+    /// unavailable.
+    pub fn namespace<S: Into<String>>(mut self, namespace: S) -> JournaldDrainBuilder {
+        let namespace = namespace.into();
+        self.config.sink = Arc::new(NamespaceSink::new(&namespace));
+        self.config.namespace = Some(namespace);
+        self
+    }
+
+    /// Send entries without the values that failed to serialize, instead of failing to
+    /// log the whole record.
+    ///
+    /// The entry gets a `SLOG_SERIALIZE_ERRORS` field with the number of failures, e.g.
+    /// malformed message ids or nested values whose `Serialize` implementation failed.
+    /// Each of those is skipped on its own. A value whose own `Value::serialize` returns
+    /// an error is different: slog stops serializing the values of the record or logger
+    /// that follow it in slog's order, which is the reverse of the order they're written
+    /// in, and there's no way to resume after it. Those values are missing as well, but
+    /// a failing record value doesn't affect the logger's values and vice versa.
+    /// Defaults to `false`.
+    pub fn skip_failed_values(mut self, skip_failed_values: bool) -> JournaldDrainBuilder {
+        self.config.skip_failed_values = skip_failed_values;
+        self
+    }
+
+    /// What to do with values whose key isn't a valid journald field name, even after
+    /// sanitizing, e.g. `"!*"`.
+    ///
+    /// Journald ignores fields with such names, so by default these values are dropped
+    /// and counted in a `SLOG_DROPPED_FIELDS` field, making it visible that values are
+    /// missing. Defaults to `InvalidKeyPolicy::Count`.
+    pub fn invalid_keys(mut self, policy: InvalidKeyPolicy) -> JournaldDrainBuilder {
+        self.config.invalid_keys = policy;
+        self
+    }
+
+    /// Read environment variables with `env` instead of from the process
+    #[cfg(test)]
+    pub(crate) fn env(mut self, env: EnvSource) -> JournaldDrainBuilder {
+        self.config.env = env;
+        self
+    }
+
+    /// Validate the configuration and create the drain.
+    pub fn build(mut self) -> Result<JournaldDrain, ConfigError> {
+        validate_prefix(&self.config.context_prefix)?;
+        validate_prefix(&self.config.record_prefix)?;
+        if self.config.error_field_prefix.is_empty() {
+            return Err(ConfigError::new("empty error field prefix".to_string()));
+        }
+        validate_prefix(&self.config.error_field_prefix)?;
+        for (name, _) in &self.config.static_fields {
+            if !is_valid_field_name(name) {
+                return Err(ConfigError::new(format!(
+                    "{:?} is not a valid journald field name",
+                    name
+                )));
+            }
+            if SOURCE_FIELDS.contains(&&**name) {
+                return Err(ConfigError::new(format!(
+                    "{:?} is a field the drain sets itself",
+                    name
+                )));
+            }
+            if WellKnownFields::NAMES.contains(&&**name) {
+                return Err(ConfigError::new(format!(
+                    "{:?} has to be set with WellKnownFields to be validated",
+                    name
+                )));
+            }
+        }
+        if let Some(ref namespace) = self.config.namespace {
+            if !sink::is_valid_namespace(namespace) {
+                return Err(ConfigError::new(format!(
+                    "{:?} is not a valid journal namespace",
+                    namespace
+                )));
+            }
+        }
+        if let Some(identifier) = self.config.syslog_identifier.take() {
+            let fields = self.config.well_known_fields.take().unwrap_or_default();
+            self.config.well_known_fields = Some(fields.default_syslog_identifier(identifier));
+        }
+        if let Some(fields) = self.config.well_known_fields.take() {
+            let fields = fields.into_fields()?;
+            self.config.static_fields.extend(fields);
+        }
+        self.config.protect_syslog_identifier = self
+            .config
+            .static_fields
+            .iter()
+            .any(|f| f.0 == "SYSLOG_IDENTIFIER");
+        if self.config.samplers.iter().flatten().any(|s| s.one_in == 0) {
+            return Err(ConfigError::new("sampling one in 0 entries".to_string()));
+        }
+        self.config.started = self.config.clock.monotonic();
+        if self.config.cmdline_field {
+            let args = std::env::args_os().map(|arg| arg.to_string_lossy().into_owned());
+            self.config.cmdline = Some(args.collect::<Vec<_>>().join(" "));
+        }
+        if self.config.invocation_id_field {
+            self.config.invocation_id = (self.config.env)("INVOCATION_ID");
+        }
+        let init_entry = self.config.init_entry;
+        let drain = JournaldDrain::from_config(self.config);
+        if init_entry {
+            drain
+                .send_init_entry()
+                .map_err(|e| ConfigError::new(format!("failed to send the init entry: {}", e)))?;
+        }
+        Ok(drain)
+    }
+}
+
+/// Fields with the level of a record, in addition to journald's `PRIORITY`.
+///
+/// Any combination can be enabled:
+///
+/// ```
+/// use slog_journald::{JournaldDrain, LevelFields};
+///
+/// let drain = JournaldDrain::builder()
+///     .level_fields(LevelFields {
+///         name: true,
+///         severity: true,
+///         ..LevelFields::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelFields {
+    /// Send slog's name for the level as `LEVEL`, e.g. `INFO`
+    pub name: bool,
+    /// Send the syslog priority number as `LEVEL_PRIORITY`, the same value as `PRIORITY`
+    /// but under a name that isn't special to journald
+    pub priority: bool,
+    /// Send the OpenTelemetry severity number as `SEVERITY`, from `1` for trace to `21`
+    /// for critical
+    pub severity: bool,
+}
+
+/// How the message of a record is sent to journald.
+///
+/// Consumers that only look at the structured fields don't need the message text,
+/// but `journalctl` shows nothing but the `MESSAGE` field in its default output.
+/// So with anything but `Full`, entries are hard to read in `journalctl` unless
+/// requesting the fields, e.g. with `journalctl -o verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageMode {
+    /// Send the whole message as `MESSAGE`.
+    Full,
+    /// Send at most this many characters of the message as `MESSAGE`.
+    ///
+    /// `Truncated(0)` sends an empty `MESSAGE`.
+    Truncated(usize),
+    /// Send an empty `MESSAGE` and the message as field `MSG` instead.
+    MsgField,
+}
+
+/// What to do with fields of the same name in one entry, see
+/// [`JournaldDrainBuilder::duplicate_keys`](struct.JournaldDrainBuilder.html#method.duplicate_keys).
+///
+/// Fields are in the order they're serialized: the logger's values, from the innermost
+/// logger out, then the record's values. So `KeepLast` prefers a key logged with the
+/// record over the same key inherited from the logger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeyPolicy {
+    /// Send all fields. Journald keeps all values of a field, `journalctl -o json`
+    /// shows them as an array.
+    KeepAll,
+    /// Send only the last field of each name.
+    KeepLast,
+    /// Send only the first field of each name.
+    KeepFirst,
+}
+
+/// How float values are formatted, see
+/// [`JournaldDrainBuilder::float_format`](struct.JournaldDrainBuilder.html#method.float_format).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// Rust's `Display`, the shortest digits that parse back to the same value and never
+    /// in scientific notation, e.g. `0.1` or `1500000`.
+    Display,
+    /// This many digits after the decimal point, e.g. `Fixed(2)` formats `1.005` as
+    /// `1.00` and `3` as `3.00`.
+    Fixed(usize),
+    /// Scientific notation with this many digits after the decimal point of the mantissa,
+    /// e.g. `Scientific(2)` formats `1500000` as `1.50e6`.
+    Scientific(usize),
+}
+
+/// How NaN and infinite float values are sent, see
+/// [`JournaldDrainBuilder::non_finite_floats`](struct.JournaldDrainBuilder.html#method.non_finite_floats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonFiniteFloats {
+    /// Rust's `Display`: `NaN`, `inf` and `-inf`.
+    Display,
+    /// `nan`, `inf` and `-inf`.
+    Lowercase,
+    /// Don't send the field.
+    Drop,
+}
+
+/// What to do with values whose key isn't a valid journald field name after
+/// sanitizing, e.g. `"!*"` or keys longer than 64 bytes, see
+/// [`JournaldDrainBuilder::invalid_keys`](struct.JournaldDrainBuilder.html#method.invalid_keys).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidKeyPolicy {
+    /// Drop the values and add a `SLOG_DROPPED_FIELDS` field with their number.
+    Count,
+    /// Send the values as `FIELD_1`, `FIELD_2` and so on, in the order they're
+    /// serialized, skipping names that other fields of the entry use.
+    Rename,
+    /// Drop the values without a trace.
+    Drop,
+}
+
+/// Durations of handling one entry, see
+/// [`JournaldDrainBuilder::on_timing`](struct.JournaldDrainBuilder.html#method.on_timing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryTiming {
+    /// Time from the start of `log` until the entry was ready to send
+    pub serialize: Duration,
+    /// Time the sink took to send the entry
+    pub send: Duration,
+}
+
+/// Why the drain didn't send a record, see
+/// [`JournaldDrainBuilder::on_drop`](struct.JournaldDrainBuilder.html#method.on_drop).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The [`kill_switch`](struct.JournaldDrainBuilder.html#method.kill_switch) was set
+    KillSwitch,
+    /// The record's level is below the
+    /// [`min_level`](struct.JournaldDrainBuilder.html#method.min_level)
+    BelowMinLevel,
+    /// The record wasn't picked by the [`sample`](struct.JournaldDrainBuilder.html#method.sample)
+    /// rate of its priority
+    Sampled,
+    /// Journald likely suppresses the entry, see
+    /// [`drop_rate_limited`](struct.JournaldDrainBuilder.html#method.drop_rate_limited)
+    RateLimited,
+}
+
+#[cfg(feature = "log_errno")]
+pub(crate) type ErrnoExtractor =
+    Box<dyn Fn(&(dyn std::error::Error + 'static)) -> Option<i32> + Send + Sync + RefUnwindSafe>;
+
+/// Reads an environment variable, replaced in tests to not depend on the process environment
+pub(crate) type EnvSource = fn(&str) -> Option<String>;
+
+pub(crate) type PriorityMap = Box<dyn Fn(Level) -> Priority + Send + Sync + RefUnwindSafe>;
+
+pub(crate) type DropCallback = Box<dyn Fn(DropReason, &Record) + Send + Sync + RefUnwindSafe>;
+
+pub(crate) type TimingCallback = Box<dyn Fn(EntryTiming) + Send + Sync + RefUnwindSafe>;
+
+/// Callback receiving the priority, message and fields of an entry.
+pub(crate) type EntryCallback = Box<dyn Fn(Priority, &str, &[Field]) + Send + Sync + RefUnwindSafe>;
+
+/// Settings of a configured drain.
+pub(crate) struct Config {
+    pub(crate) context_prefix: String,
+    pub(crate) record_prefix: String,
+    pub(crate) message_only: bool,
+    pub(crate) message_id_key: String,
+    pub(crate) source_timestamp_key: Option<String>,
+    pub(crate) unit_fields: bool,
+    pub(crate) sink: Arc<dyn JournalSink>,
+    pub(crate) rate_limit: Option<RateLimitEstimator>,
+    pub(crate) message_mode: MessageMode,
+    pub(crate) monotonic_time: bool,
+    /// When the drain was built
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Monotonic time of the clock when the drain was built
+    pub(crate) started: Duration,
+    #[cfg(feature = "nested-values")]
+    pub(crate) serde_formatters: HashMap<String, SerdeFormatter>,
+    #[cfg(feature = "nested-values")]
+    pub(crate) indexed_sequences: bool,
+    pub(crate) max_field_len: Option<usize>,
+    pub(crate) max_message_len: Option<usize>,
+    pub(crate) truncation_marker: String,
+    pub(crate) crate_field: bool,
+    pub(crate) merge_separator: Option<String>,
+    pub(crate) dry_run: bool,
+    pub(crate) on_dry_run: Option<EntryCallback>,
+    pub(crate) on_send: Option<EntryCallback>,
+    pub(crate) priority_sinks: [Option<Arc<dyn JournalSink>>; 8],
+    pub(crate) protect_reserved_fields: bool,
+    pub(crate) object_fields: bool,
+    pub(crate) startup_field: bool,
+    pub(crate) startup_sent: AtomicBool,
+    pub(crate) info_as_notice: bool,
+    pub(crate) samplers: [Option<Sampler>; 8],
+    pub(crate) integer_type_fields: bool,
+    pub(crate) trim_values: bool,
+    pub(crate) elapsed_field: bool,
+    pub(crate) invocation_id_field: bool,
+    pub(crate) invocation_id: Option<String>,
+    pub(crate) env: EnvSource,
+    pub(crate) static_fields: Vec<Field>,
+    pub(crate) debug_dump: bool,
+    pub(crate) max_collision_suffix: Option<usize>,
+    pub(crate) cmdline_field: bool,
+    pub(crate) cmdline: Option<String>,
+    pub(crate) static_fields_as_defaults: bool,
+    pub(crate) exemplar_trace_id_key: Option<String>,
+    pub(crate) catch_format_panics: bool,
+    pub(crate) content_hash: bool,
+    pub(crate) error_field_prefix: String,
+    pub(crate) init_entry: bool,
+    pub(crate) dots_as_double_underscore: bool,
+    pub(crate) on_timing: Option<TimingCallback>,
+    pub(crate) max_send_retries: u32,
+    pub(crate) send_retries_field: bool,
+    pub(crate) expected_fields: usize,
+    pub(crate) payload_json: bool,
+    pub(crate) epoch_iso_fields: bool,
+    pub(crate) level_fields: LevelFields,
+    pub(crate) kill_switch: Option<Arc<AtomicBool>>,
+    pub(crate) min_level: Level,
+    pub(crate) drop_rate_limited: bool,
+    pub(crate) on_drop: Option<DropCallback>,
+    pub(crate) priority_map: Option<PriorityMap>,
+    pub(crate) sort_fields: bool,
+    pub(crate) version_field: bool,
+    pub(crate) test_mode: bool,
+    pub(crate) protect_source_fields: bool,
+    pub(crate) well_known_fields: Option<WellKnownFields>,
+    pub(crate) syslog_identifier: Option<String>,
+    /// Whether the drain sends a `SYSLOG_IDENTIFIER` of its own
+    pub(crate) protect_syslog_identifier: bool,
+    pub(crate) code_function_field: bool,
+    pub(crate) duplicate_keys: DuplicateKeyPolicy,
+    pub(crate) effective_id_fields: bool,
+    pub(crate) message_fields: Vec<String>,
+    pub(crate) thread_fields: bool,
+    pub(crate) key_sanitizer: Option<Arc<dyn KeySanitizer>>,
+    #[cfg(feature = "log_errno")]
+    pub(crate) errno_extractors: Vec<ErrnoExtractor>,
+    pub(crate) float_format: FloatFormat,
+    pub(crate) non_finite_floats: NonFiniteFloats,
+    pub(crate) namespace: Option<String>,
+    pub(crate) skip_failed_values: bool,
+    pub(crate) invalid_keys: InvalidKeyPolicy,
+}
+
+impl Config {
+    /// Monotonic time since the drain was built
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.monotonic().saturating_sub(self.started)
+    }
+
+    /// The priority of records with `level`
+    pub(crate) fn priority(&self, level: Level) -> Priority {
+        match self.priority_map {
+            Some(ref map) => map(level),
+            None => level_to_priority(level, self.info_as_notice),
+        }
+    }
+
+    /// The sink for entries of `priority`
+    pub(crate) fn sink_for(&self, priority: Priority) -> &dyn JournalSink {
+        match self.priority_sinks[u8::from(priority) as usize] {
+            Some(ref sink) => &**sink,
+            None => &*self.sink,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            context_prefix: String::new(),
+            record_prefix: String::new(),
+            message_only: false,
+            message_id_key: "message_id".to_string(),
+            source_timestamp_key: None,
+            unit_fields: false,
+            sink: Arc::new(JournaldSink),
+            rate_limit: None,
+            message_mode: MessageMode::Full,
+            monotonic_time: false,
+            clock: Arc::new(SystemClock),
+            started: SystemClock.monotonic(),
+            #[cfg(feature = "nested-values")]
+            serde_formatters: HashMap::new(),
+            #[cfg(feature = "nested-values")]
+            indexed_sequences: true,
+            max_field_len: Some(DEFAULT_MAX_LEN),
+            max_message_len: Some(DEFAULT_MAX_LEN),
+            truncation_marker: "…[truncated {} bytes]".to_string(),
+            crate_field: false,
+            merge_separator: None,
+            dry_run: false,
+            on_dry_run: None,
+            on_send: None,
+            priority_sinks: Default::default(),
+            protect_reserved_fields: false,
+            object_fields: false,
+            startup_field: false,
+            startup_sent: AtomicBool::new(false),
+            info_as_notice: true,
+            samplers: Default::default(),
+            integer_type_fields: false,
+            trim_values: false,
+            elapsed_field: false,
+            invocation_id_field: false,
+            invocation_id: None,
+            env: |name| std::env::var(name).ok(),
+            static_fields: Vec::new(),
+            debug_dump: false,
+            max_collision_suffix: None,
+            cmdline_field: false,
+            cmdline: None,
+            static_fields_as_defaults: false,
+            exemplar_trace_id_key: None,
+            catch_format_panics: false,
+            content_hash: false,
+            error_field_prefix: "ERROR".to_string(),
+            init_entry: false,
+            dots_as_double_underscore: false,
+            on_timing: None,
+            max_send_retries: 0,
+            send_retries_field: false,
+            expected_fields: 0,
+            payload_json: false,
+            epoch_iso_fields: false,
+            level_fields: LevelFields::default(),
+            kill_switch: None,
+            min_level: Level::Trace,
+            drop_rate_limited: false,
+            on_drop: None,
+            priority_map: None,
+            sort_fields: false,
+            version_field: false,
+            test_mode: false,
+            protect_source_fields: true,
+            well_known_fields: None,
+            syslog_identifier: None,
+            protect_syslog_identifier: false,
+            code_function_field: false,
+            duplicate_keys: DuplicateKeyPolicy::KeepAll,
+            effective_id_fields: false,
+            message_fields: Vec::new(),
+            thread_fields: false,
+            key_sanitizer: None,
+            #[cfg(feature = "log_errno")]
+            errno_extractors: Vec::new(),
+            float_format: FloatFormat::Display,
+            non_finite_floats: NonFiniteFloats::Display,
+            namespace: None,
+            skip_failed_values: false,
+            invalid_keys: InvalidKeyPolicy::Count,
+        }
+    }
+}
+
+/// Error for configuration rejected by [`JournaldDrainBuilder::build`](struct.JournaldDrainBuilder.html#method.build).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    description: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new<S: Into<String>>(description: S) -> ConfigError {
+        ConfigError {
+            description: description.into(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(&self.description)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Whether journald accepts `name` as field name of an entry sent by a client.
+///
+/// This is a `const fn` so [`journald_fields!`](macro.journald_fields.html) can check
+/// its names at compile time.
+#[doc(hidden)]
+pub const fn is_valid_field_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    if bytes.is_empty() || bytes.len() > 64 || !bytes[0].is_ascii_uppercase() {
+        return false;
+    }
+    let mut i = 1;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if !(b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_') {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// An empty prefix is no prefix at all. Otherwise the prefix ends up at the start
+/// of a field name, so it may not start with an underscore or digit.
+fn validate_prefix(prefix: &str) -> Result<(), ConfigError> {
+    let valid_start = match prefix.chars().next() {
+        None => return Ok(()),
+        Some(c) => c.is_ascii_uppercase(),
+    };
+    let valid_chars = prefix
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if valid_start && valid_chars {
+        Ok(())
+    } else {
+        Err(ConfigError::new(format!(
+            "{:?} is not a valid journald field name prefix",
+            prefix
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_validation() {
+        assert!(validate_prefix("").is_ok());
+        assert!(validate_prefix("CTX_").is_ok());
+        assert!(validate_prefix("A1").is_ok());
+        assert!(validate_prefix("ctx_").is_err());
+        assert!(validate_prefix("_CTX").is_err());
+        assert!(validate_prefix("1CTX").is_err());
+        assert!(validate_prefix("CTX.").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Capturing entries instead of sending them, for tests.
+
+use std::sync::{Arc, Mutex};
+
+use libsystemd::errors::SdError;
+use libsystemd::logging::Priority;
+use slog::{Drain, Level, OwnedKVList, Record};
+
+use builder::{ConfigError, JournaldDrainBuilder};
+use ring_buffer::JournalEntry;
+use sink::{Field, JournalSink};
+use JournaldDrain;
+
+/// Entries captured by a [`TestJournaldDrain`](struct.TestJournaldDrain.html)
+pub type CapturedEntries = Arc<Mutex<Vec<JournalEntry>>>;
+
+/// A `JournaldDrain` that keeps the entries instead of sending them to journald.
+///
+/// The records are serialized exactly like by the drain it's built from, so tests can
+/// check the field names, values and priorities journald would get, without a running
+/// systemd:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog::Drain;
+/// use slog_journald::TestJournaldDrain;
+///
+/// fn main() {
+///     let drain = TestJournaldDrain::new();
+///     let entries = drain.entries();
+///     let log = slog::Logger::root(drain.fuse(), o!());
+///     warn!(log, "Disk almost full"; "foo" => "bar");
+///
+///     let entries = entries.lock().unwrap();
+///     assert_eq!(entries[0].message, "Disk almost full");
+///     assert_eq!(u8::from(entries[0].priority), 4);
+///     assert_eq!(entries[0].field("FOO"), Some("bar"));
+/// }
+/// ```
+///
+/// Binary values are captured hex-encoded, like sinks without binary support send them.
+pub struct TestJournaldDrain {
+    drain: JournaldDrain,
+    entries: CapturedEntries,
+}
+
+impl TestJournaldDrain {
+    /// Capture the entries of a drain with the default configuration.
+    pub fn new() -> TestJournaldDrain {
+        TestJournaldDrain::with_builder(JournaldDrainBuilder::new())
+            .expect("the default configuration is valid")
+    }
+
+    /// Capture the entries of the drain configured by `builder`.
+    ///
+    /// This replaces the sinks of the builder, including the ones for single priorities.
+    pub fn with_builder(builder: JournaldDrainBuilder) -> Result<TestJournaldDrain, ConfigError> {
+        let entries = CapturedEntries::default();
+        let sink = Capturing(entries.clone());
+        let drain = builder
+            .wrap_sinks(|_| Arc::new(sink.clone()) as Arc<dyn JournalSink>)
+            .build()?;
+        Ok(TestJournaldDrain { drain, entries })
+    }
+
+    /// The entries captured so far, shared with the drain.
+    pub fn entries(&self) -> CapturedEntries {
+        self.entries.clone()
+    }
+}
+
+impl Default for TestJournaldDrain {
+    fn default() -> TestJournaldDrain {
+        TestJournaldDrain::new()
+    }
+}
+
+impl Drain for TestJournaldDrain {
+    type Ok = ();
+    type Err = ::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), ::Error> {
+        self.drain.log(record, values)
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.drain.is_enabled(level)
+    }
+}
+
+/// Sink appending the entries to a `CapturedEntries`
+#[derive(Clone)]
+struct Capturing(CapturedEntries);
+
+impl JournalSink for Capturing {
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+        let mut entries = match self.0.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        entries.push(JournalEntry {
+            priority,
+            message: message.to_string(),
+            fields: fields.to_vec(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Time sources for the timestamp fields.
+
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of the current time for fields like `MONOTONIC_NS` and `ELAPSED_MS`.
+///
+/// The drain uses [`SystemClock`](struct.SystemClock.html) by default, a different clock
+/// can be installed with [`JournaldDrainBuilder::clock`](struct.JournaldDrainBuilder.html#method.clock),
+/// e.g. [`ManualClock`](struct.ManualClock.html) for deterministic fields in tests.
+pub trait Clock: Send + Sync + RefUnwindSafe {
+    /// Time since an arbitrary, fixed point, never going backwards.
+    fn monotonic(&self) -> Duration;
+
+    /// The current wall-clock time.
+    fn realtime(&self) -> SystemTime;
+
+    /// Block for `duration`, e.g. between retries of a send.
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Format seconds since the Unix epoch as RFC 3339 UTC time, like `2023-11-14T22:13:20Z`.
+///
+/// Returns `None` for years outside of 0 to 9999, which RFC 3339 can't represent.
+pub(crate) fn rfc3339(epoch_secs: i64) -> Option<String> {
+    let days = epoch_secs.div_euclid(86_400);
+    let secs = epoch_secs.rem_euclid(86_400);
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    ))
+}
+
+/// The clocks of the operating system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn monotonic(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+
+    fn realtime(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep one to advance the clock of a drain:
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use slog_journald::{JournaldDrain, ManualClock};
+///
+/// let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+/// let drain = JournaldDrain::builder().clock(clock.clone()).build().unwrap();
+/// clock.advance(Duration::from_millis(20));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<(Duration, SystemTime)>>,
+}
+
+impl ManualClock {
+    /// Create a clock at wall-clock time `realtime` and monotonic time zero.
+    pub fn new(realtime: SystemTime) -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new((Duration::from_secs(0), realtime))),
+        }
+    }
+
+    /// Move both the monotonic and the wall-clock time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.lock();
+        now.0 += duration;
+        now.1 += duration;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (Duration, SystemTime)> {
+        match self.now.lock() {
+            Ok(now) => now,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn monotonic(&self) -> Duration {
+        self.lock().0
+    }
+
+    fn realtime(&self) -> SystemTime {
+        self.lock().1
+    }
+
+    /// Advances the clock by `duration` instead of blocking.
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_rfc3339() {
+        assert_eq!(rfc3339(0).unwrap(), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(1_700_000_000).unwrap(), "2023-11-14T22:13:20Z");
+        assert_eq!(rfc3339(951_782_400).unwrap(), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(-1).unwrap(), "1969-12-31T23:59:59Z");
+        assert_eq!(rfc3339(253_402_300_799).unwrap(), "9999-12-31T23:59:59Z");
+        assert_eq!(rfc3339(253_402_300_800), None);
+        assert_eq!(rfc3339(-62_167_219_201), None);
+    }
+}
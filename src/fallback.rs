@@ -0,0 +1,83 @@
+//! Logging somewhere else when journald isn't available.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use libsystemd::errors::SdError;
+use slog::{Drain, OwnedKVList, Record};
+
+use sink;
+use {Error, JournaldDrain};
+
+/// A `JournaldDrain` that hands records it fails to send to another drain, created with
+/// [`JournaldDrain::with_fallback`](struct.JournaldDrain.html#method.with_fallback).
+///
+/// Without journald, e.g. in CI, a container or outside of systemd, every entry fails to
+/// send. This logs them with the fallback drain instead, e.g. a terminal drain writing to
+/// stderr:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog::Drain;
+/// use slog_journald::JournaldDrain;
+///
+/// fn main() {
+///     let stderr = slog::Discard; // e.g. `slog_term::FullFormat` over stderr
+///     let drain = JournaldDrain.with_fallback(stderr);
+///     let log = slog::Logger::root(drain.fuse(), o!());
+///     info!(log, "Logged to journald if available");
+/// }
+/// ```
+///
+/// If sending fails because journald's socket doesn't exist or doesn't accept
+/// connections, the drain remembers that journald is unavailable and sends all further
+/// records to the fallback right away. Other errors only send the failed record to the
+/// fallback.
+pub struct FallbackDrain<D> {
+    drain: JournaldDrain,
+    fallback: D,
+    unavailable: AtomicBool,
+}
+
+impl<D> FallbackDrain<D> {
+    pub(crate) fn new(drain: JournaldDrain, fallback: D) -> FallbackDrain<D> {
+        FallbackDrain {
+            drain,
+            fallback,
+            unavailable: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether records are still sent to journald first.
+    pub fn journald_available(&self) -> bool {
+        !self.unavailable.load(Ordering::Relaxed)
+    }
+}
+
+impl<D: Drain> Drain for FallbackDrain<D> {
+    type Ok = ();
+    type Err = D::Err;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), D::Err> {
+        if self.journald_available() {
+            match self.drain.log(record, values) {
+                Ok(()) => return Ok(()),
+                Err(Error::Journald(ref e)) if is_unavailable(e) => {
+                    self.unavailable.store(true, Ordering::Relaxed);
+                }
+                Err(_) => {}
+            }
+        }
+        self.fallback.log(record, values).map(|_| ())
+    }
+}
+
+/// Whether sending failed because there's no journald listening on the socket
+fn is_unavailable(error: &SdError) -> bool {
+    match sink::raw_os_error(error) {
+        Some(errno) => errno == libc::ENOENT || errno == libc::ECONNREFUSED,
+        None => false,
+    }
+}
@@ -6,6 +6,21 @@
 //! This crate supports specialized handling of logged errors via features.
 //! Look into `Cargo.toml` for more information.
 //!
+//! With the `nested-values` feature, values logged with `slog::Serde` can be formatted
+//! by a formatter registered for their serde type name with
+//! [`JournaldDrainBuilder::serde_formatter`](struct.JournaldDrainBuilder.html#method.serde_formatter).
+//! The formatter receives the value converted to JSON, so domain types can be sent
+//! as a single readable field even though slog erases their type.
+//!
+//! Other nested values are flattened into one field per leaf value. The field name is
+//! the sanitized path of keys leading to the value, e.g. a struct `{ user: { id: 5 } }`
+//! logged under `ctx` becomes `CTX_USER_ID=5`. Enums are sent as the variant name,
+//! with the data of the variant below the variant name: `State::Failed { code: 3 }`
+//! logged as `state` becomes `STATE=Failed` and `STATE_FAILED_CODE=3`. Sequences are
+//! sent as one field per element with index suffixes, like `CTX_TAGS_0` and
+//! `CTX_TAGS_1`, or as a single JSON array after
+//! [`JournaldDrainBuilder::indexed_sequences(false)`](struct.JournaldDrainBuilder.html#method.indexed_sequences).
+//!
 //! # Examples
 //! ```
 //! #[macro_use]
@@ -20,49 +35,726 @@
 //!     info!(root, "Testing journald"; "foo" => "bar");
 //! }
 //! ```
+//!
+//! The drain can be configured with a [`JournaldDrainBuilder`](struct.JournaldDrainBuilder.html):
+//!
+//! ```
+//! #[macro_use]
+//! extern crate slog;
+//! extern crate slog_journald;
+//!
+//! use slog::*;
+//! use slog_journald::*;
+//!
+//! fn main() {
+//!     let drain = JournaldDrain::builder()
+//!         .context_prefix("CTX_")
+//!         .build()
+//!         .unwrap();
+//!     let root = Logger::root(drain.ignore_res(), o!("build_di" => "12344"));
+//!     info!(root, "Testing journald"; "foo" => "bar");
+//! }
+//! ```
+//!
+//! Fields that every entry needs, like the service name, can be given to the drain
+//! with [`syslog_identifier`](struct.JournaldDrainBuilder.html#method.syslog_identifier)
+//! and [`add_field`](struct.JournaldDrainBuilder.html#method.add_field) instead of
+//! the root logger's `o!`. Their names are taken as written and checked once by
+//! `build`, and they're sent ahead of the fields of the record:
+//!
+//! ```
+//! #[macro_use]
+//! extern crate slog;
+//! extern crate slog_journald;
+//!
+//! use slog::*;
+//! use slog_journald::*;
+//!
+//! fn main() {
+//!     let container = String::from("web-1");
+//!     let drain = JournaldDrain::builder()
+//!         .syslog_identifier("myservice")
+//!         .add_field("CONTAINER_NAME", container)
+//!         .build()
+//!         .unwrap();
+//!     let root = Logger::root(drain.ignore_res(), o!());
+//!     info!(root, "Testing journald"; "foo" => "bar");
+//! }
+//! ```
 
 #![warn(missing_docs)]
 
+extern crate libc;
 extern crate libsystemd;
+#[cfg(feature = "log_errno_nix")]
+extern crate nix;
+#[cfg(feature = "nested-values")]
+extern crate serde;
+#[cfg_attr(test, macro_use)]
 extern crate slog;
+#[cfg(all(test, feature = "nested-values"))]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "nested-values")]
+extern crate serde_json;
+#[cfg(feature = "tracing-integration")]
+extern crate tracing;
+#[cfg(all(test, feature = "tracing-integration"))]
+extern crate tracing_subscriber;
+
+mod background;
+mod builder;
+mod capture;
+mod clock;
+mod fallback;
+mod native;
+#[cfg(feature = "nested-values")]
+mod nested;
+mod path;
+mod payload;
+mod rate_limit;
+#[cfg(feature = "remote")]
+mod remote;
+mod ring_buffer;
+mod sampling;
+mod sanitizer;
+mod sink;
+mod well_known;
+
+pub use background::{AsyncJournaldDrain, OverflowPolicy};
+#[doc(hidden)]
+pub use builder::is_valid_field_name as __is_valid_field_name;
+pub use builder::{
+    ConfigError, DropReason, DuplicateKeyPolicy, EntryTiming, FloatFormat, InvalidKeyPolicy,
+    JournaldDrainBuilder, LevelFields, MessageMode, NonFiniteFloats, DEFAULT_MAX_LEN,
+};
+pub use capture::{CapturedEntries, TestJournaldDrain};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use fallback::FallbackDrain;
+pub use libsystemd::logging::Priority;
+pub use path::{BinaryValue, PathValue};
+#[cfg(feature = "remote")]
+pub use remote::RemoteSink;
+pub use ring_buffer::{JournalEntry, RingBuffer, RingBufferJournaldDrain};
+pub use sanitizer::{DefaultSanitizer, KeySanitizer};
+pub use sink::{BinaryField, Field, JournalSink, JournaldSink};
+pub use well_known::{MessageId, WellKnownFields};
 
 #[allow(deprecated, unused_imports)]
 use std::ascii::AsciiExt;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::{Display, Formatter, Write};
+use std::io;
+use std::panic::{self, AssertUnwindSafe, RefUnwindSafe};
 
+use builder::Config;
 use libsystemd::errors::SdError;
-use libsystemd::logging::{journal_send, Priority};
-use slog::{Drain, Key, Level, OwnedKVList, Record, KV};
+use libsystemd::id128::Id128;
+use rate_limit::RateLimitEstimator;
+use sampling::Sampler;
+use slog::{
+    BytesKind, Drain, Key, Level, LevelFilter, Logger, OwnedKV, OwnedKVList, Record,
+    SendSyncRefUnwindSafeKV, KV,
+};
 use std::borrow::Cow;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::Duration;
 
 /// Drain records and send to journald as structured data.
 ///
 /// Journald requires keys to be uppercase alphanumeric, so logging keys
 /// are capitalized and all non-alpha-numeric letters are converted to underscores.
-pub struct JournaldDrain;
+/// Field names also have to start with a letter, so anything before the first letter
+/// of a key is dropped, e.g. `1st_try` becomes `ST_TRY`.
+/// Keys that already are valid field names, like `REQUEST_ID`, are used as written.
+///
+/// The `JournaldDrain` constant is a drain with the default configuration.
+/// Use [`JournaldDrain::builder`](#method.builder) to configure one.
+pub struct JournaldDrain {
+    config: Option<Arc<Config>>,
+}
+
+/// Drain with the default configuration.
+///
+/// This keeps `JournaldDrain` usable like the unit struct it used to be.
+#[allow(non_upper_case_globals)]
+pub const JournaldDrain: JournaldDrain = JournaldDrain { config: None };
+
+impl JournaldDrain {
+    /// Start configuring a drain.
+    pub fn builder() -> JournaldDrainBuilder {
+        JournaldDrainBuilder::new()
+    }
+
+    /// Create a drain mapping the levels of records to journald priorities with `map`.
+    ///
+    /// This is a shortcut for
+    /// [`JournaldDrainBuilder::priority_map`](struct.JournaldDrainBuilder.html#method.priority_map)
+    /// with the otherwise default configuration.
+    pub fn with_priority_map<F>(map: F) -> JournaldDrain
+    where
+        F: Fn(Level) -> Priority + Send + Sync + RefUnwindSafe + 'static,
+    {
+        JournaldDrainBuilder::new()
+            .priority_map(map)
+            .build()
+            .expect("the default configuration is valid")
+    }
+
+    /// Create a drain sending to the journal of the log namespace `namespace`.
+    ///
+    /// This is a shortcut for
+    /// [`JournaldDrainBuilder::namespace`](struct.JournaldDrainBuilder.html#method.namespace)
+    /// with the otherwise default configuration, failing if `namespace` isn't a valid
+    /// namespace name.
+    pub fn with_namespace<S: Into<String>>(namespace: S) -> Result<JournaldDrain, ConfigError> {
+        JournaldDrainBuilder::new().namespace(namespace).build()
+    }
+
+    /// Log the records this drain fails to send with `fallback` instead, see
+    /// [`FallbackDrain`](struct.FallbackDrain.html).
+    pub fn with_fallback<D: Drain>(self, fallback: D) -> FallbackDrain<D> {
+        FallbackDrain::new(self, fallback)
+    }
+
+    /// The priority a record of `level` is sent with by default: `Info` as `Notice`,
+    /// `Debug` as `Info` and `Trace` as `Debug`, the other levels as the priority of
+    /// the same name.
+    pub fn default_priority(level: Level) -> Priority {
+        level_to_priority(level, true)
+    }
+
+    pub(crate) fn from_config(config: Config) -> JournaldDrain {
+        JournaldDrain {
+            config: Some(Arc::new(config)),
+        }
+    }
+
+    fn config(&self) -> &Config {
+        static DEFAULT: OnceLock<Config> = OnceLock::new();
+        match self.config {
+            Some(ref config) => config,
+            None => DEFAULT.get_or_init(Config::default),
+        }
+    }
+
+    /// The fields this drain sends to journald for `record`, starting with `PRIORITY`
+    /// and `MESSAGE`.
+    ///
+    /// This is the serialization `log` uses, for drains sending records elsewhere with
+    /// the same field names and values. Binary values are hex-encoded, like sinks without
+    /// binary support get them. The record is neither sent nor checked against the
+    /// [`min_level`](struct.JournaldDrainBuilder.html#method.min_level) or sampling, and
+    /// fields describing the sending, like `STARTUP` or `SEND_RETRIES`, are missing.
+    ///
+    /// ```
+    /// extern crate slog;
+    /// extern crate slog_journald;
+    ///
+    /// use slog::{Drain, OwnedKVList, Record};
+    /// use slog_journald::{Error, JournaldDrain};
+    ///
+    /// /// Sends records to journald and prints the same fields
+    /// struct Tee(JournaldDrain);
+    ///
+    /// impl Drain for Tee {
+    ///     type Ok = ();
+    ///     type Err = Error;
+    ///
+    ///     fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Error> {
+    ///         for (name, value) in self.0.journald_fields(record, values)? {
+    ///             println!("{}={}", name, value);
+    ///         }
+    ///         self.0.log(record, values)
+    ///     }
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn journald_fields(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<Vec<Field>, Error> {
+        let config = self.config();
+        let priority = config.priority(record.level());
+        let (message, mut serializer) = self.entry(record, logger_values)?;
+        let mut fields = Vec::with_capacity(serializer.fields.len() + 2);
+        fields.push((Cow::Borrowed("PRIORITY"), u8::from(priority).to_string()));
+        fields.push((Cow::Borrowed("MESSAGE"), message));
+        let mut rest = std::mem::take(&mut serializer.fields);
+        rest.extend(sink::binary_as_text(&serializer.binary));
+        if config.sort_fields {
+            sort_fields(&mut rest);
+        }
+        fields.append(&mut rest);
+        Ok(fields)
+    }
+
+    /// The message and fields of the entry for a record, before the fields about sending it
+    fn entry(
+        &self,
+        info: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<(String, Serializer<'_>), ::Error> {
+        let config = self.config();
+        let mut serializer = self.serialize(info, logger_values)?;
+        let message = message(config, info, &mut serializer);
+        dedup_fields(&mut serializer.fields, config.duplicate_keys);
+        if serializer.truncated {
+            serializer
+                .fields
+                .push((Cow::Borrowed("TRUNCATED"), "1".to_string()));
+        }
+        if config.content_hash {
+            let hash = content_hash(&message, &serializer.fields);
+            serializer
+                .fields
+                .push((Cow::Borrowed("CONTENT_HASH"), hash));
+        }
+        Ok((message, serializer))
+    }
+
+    /// Collect the journald fields for a record, excluding `MESSAGE` and `PRIORITY`.
+    fn serialize(
+        &self,
+        info: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<Serializer<'_>, ::Error> {
+        let config = self.config();
+        let mut serializer = Serializer::new(config);
+        if config.message_only {
+            return Ok(serializer);
+        }
+        if !config.static_fields_as_defaults {
+            serializer
+                .fields
+                .extend(config.static_fields.iter().cloned());
+        }
+        serializer.add_field(Cow::Borrowed("CODE_FILE"), info.file().to_string());
+        serializer.add_field(Cow::Borrowed("CODE_LINE"), info.line().to_string());
+        serializer.add_field(Cow::Borrowed("CODE_MODULE"), info.module().to_string());
+        serializer.add_field(Cow::Borrowed("CODE_FUNC"), info.function().to_string());
+        if config.code_function_field {
+            serializer.add_field(Cow::Borrowed("CODE_FUNCTION"), info.function().to_string());
+        }
+        let level_fields = config.level_fields;
+        if level_fields.name {
+            let name = info.level().as_str().to_string();
+            serializer.add_field(Cow::Borrowed("LEVEL"), name);
+        }
+        if level_fields.priority {
+            let priority = config.priority(info.level());
+            serializer.add_field(
+                Cow::Borrowed("LEVEL_PRIORITY"),
+                u8::from(priority).to_string(),
+            );
+        }
+        if level_fields.severity {
+            let severity = otel_severity(info.level());
+            serializer.add_field(Cow::Borrowed("SEVERITY"), severity.to_string());
+        }
+        if config.crate_field {
+            serializer.add_field(
+                Cow::Borrowed("CRATE"),
+                crate_name(info.module()).to_string(),
+            );
+        }
+        if config.test_mode {
+            serializer.add_field(Cow::Borrowed("TEST_MODE"), "1".to_string());
+        }
+        if config.version_field {
+            serializer.add_field(
+                Cow::Borrowed("SLOG_JOURNALD_VERSION"),
+                env!("CARGO_PKG_VERSION").to_string(),
+            );
+        }
+        if config.monotonic_time {
+            let nanos = config.elapsed().as_nanos();
+            serializer.add_field(Cow::Borrowed("MONOTONIC_NS"), nanos.to_string());
+        }
+        if let Some(ref cmdline) = config.cmdline {
+            serializer.add_field(Cow::Borrowed("APP_CMDLINE"), cmdline.clone());
+        }
+        if let Some(ref invocation_id) = config.invocation_id {
+            serializer.add_field(Cow::Borrowed("APP_INVOCATION_ID"), invocation_id.clone());
+        }
+        if config.effective_id_fields {
+            // Both always succeed
+            let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+            serializer.add_field(Cow::Borrowed("APP_EUID"), euid.to_string());
+            serializer.add_field(Cow::Borrowed("APP_EGID"), egid.to_string());
+        }
+        if config.thread_fields {
+            // Always succeeds
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+            serializer.add_field(Cow::Borrowed("TID"), tid.to_string());
+            if let Some(name) = thread::current().name() {
+                serializer.add_field(Cow::Borrowed("THREAD_NAME"), name.to_string());
+            }
+        }
+        if config.elapsed_field {
+            let millis = config.elapsed().as_millis();
+            serializer.add_field(Cow::Borrowed("ELAPSED_MS"), millis.to_string());
+        }
+        #[cfg(feature = "tracing-integration")]
+        {
+            let span = tracing::Span::current();
+            if let Some(id) = span.id() {
+                serializer.add_field(Cow::Borrowed("SPAN_ID"), id.into_u64().to_string());
+            }
+            if let Some(metadata) = span.metadata() {
+                serializer.add_field(Cow::Borrowed("SPAN_NAME"), metadata.name().to_string());
+            }
+        }
+
+        serializer.prefix = &config.context_prefix;
+        let result = logger_values.serialize(info, &mut serializer);
+        serializer.recover(result)?;
+        serializer.prefix = &config.record_prefix;
+        let result = info.kv().serialize(info, &mut serializer);
+        serializer.recover(result)?;
+        if config.payload_json {
+            match payload::payload_json(info, logger_values) {
+                Ok(json) => serializer.add_field(Cow::Borrowed("PAYLOAD_JSON"), json),
+                // The payload serializes the same values, don't count their errors twice
+                Err(_) if serializer.errors > 0 => {}
+                Err(e) => serializer.recover(Err(e))?,
+            }
+        }
+        if config.static_fields_as_defaults {
+            for field in &config.static_fields {
+                if !serializer.has_field(&field.0) {
+                    serializer.fields.push(field.clone());
+                }
+            }
+        }
+        if serializer.dropped_collisions > 0 {
+            let dropped = serializer.dropped_collisions.to_string();
+            serializer
+                .fields
+                .push((Cow::Borrowed("COLLISIONS_DROPPED"), dropped));
+        }
+        if serializer.invalid_keys > 0 && config.invalid_keys == InvalidKeyPolicy::Count {
+            let dropped = serializer.invalid_keys.to_string();
+            serializer
+                .fields
+                .push((Cow::Borrowed("SLOG_DROPPED_FIELDS"), dropped));
+        }
+        if serializer.errors > 0 {
+            let errors = serializer.errors.to_string();
+            serializer
+                .fields
+                .push((Cow::Borrowed("SLOG_SERIALIZE_ERRORS"), errors));
+        }
+        if serializer.invalid_keys > 0 && config.invalid_keys == InvalidKeyPolicy::Rename {
+            serializer.name_renamed();
+        }
+        Ok(serializer)
+    }
+
+    /// Send the entry of [`JournaldDrainBuilder::init_entry`](struct.JournaldDrainBuilder.html#method.init_entry)
+    pub(crate) fn send_init_entry(&self) -> Result<(), SdError> {
+        let config = self.config();
+        let mut fields = config.static_fields.clone();
+        fields.push((Cow::Borrowed("DRAIN_INIT"), "1".to_string()));
+        let version = env!("CARGO_PKG_VERSION").to_string();
+        fields.push((Cow::Borrowed("DRAIN_VERSION"), version));
+        let mode = format!("{:?}", config.message_mode);
+        fields.push((Cow::Borrowed("DRAIN_MESSAGE_MODE"), mode));
+        if let Some(max_len) = config.max_field_len {
+            fields.push((Cow::Borrowed("DRAIN_MAX_FIELD_LEN"), max_len.to_string()));
+        }
+
+        let message = "slog-journald drain initialized";
+        if config.dry_run {
+            if let Some(ref callback) = config.on_dry_run {
+                callback(Priority::Info, message, &fields);
+            }
+            return Ok(());
+        }
+        config
+            .sink_for(Priority::Info)
+            .send(Priority::Info, message, &fields)
+    }
+
+    /// Create a root logger sending records of at least `min_level` to this drain.
+    ///
+    /// Errors from sending are ignored.
+    pub fn filtered_logger<T>(self, min_level: Level, values: OwnedKV<T>) -> Logger
+    where
+        T: SendSyncRefUnwindSafeKV + 'static,
+    {
+        Logger::root(LevelFilter::new(self, min_level).ignore_res(), values)
+    }
+
+    /// Estimated number of entries journald suppressed due to rate limiting.
+    ///
+    /// Always `0` unless enabled with
+    /// [`JournaldDrainBuilder::estimate_rate_limit`](struct.JournaldDrainBuilder.html#method.estimate_rate_limit).
+    pub fn rate_limited_estimate(&self) -> u64 {
+        self.config()
+            .rate_limit
+            .as_ref()
+            .map_or(0, RateLimitEstimator::suppressed)
+    }
+}
 
 impl Drain for JournaldDrain {
     type Ok = ();
     type Err = ::Error;
 
     fn log(&self, info: &Record, logger_values: &OwnedKVList) -> Result<(), ::Error> {
-        let mut serializer = Serializer::new();
-        serializer.add_field(Cow::Borrowed("CODE_FILE"), info.file().to_string());
-        serializer.add_field(Cow::Borrowed("CODE_LINE"), info.line().to_string());
-        serializer.add_field(Cow::Borrowed("CODE_MODULE"), info.module().to_string());
-        serializer.add_field(Cow::Borrowed("CODE_FUNCTION"), info.function().to_string());
+        let config = self.config();
+        let priority = config.priority(info.level());
+        if let Some(reason) = drop_reason(config, info, priority) {
+            if let Some(ref callback) = config.on_drop {
+                callback(reason, info);
+            }
+            return Ok(());
+        }
+        let sampler = config.samplers[u8::from(priority) as usize].as_ref();
+
+        let serialize_start = config.on_timing.as_ref().map(|_| config.clock.monotonic());
+        let (message, mut serializer) = match self.entry(info, logger_values) {
+            Ok(entry) => entry,
+            Err(e) => {
+                if let Some(sampler) = sampler {
+                    sampler.restore_suppressed(1);
+                }
+                return Err(e);
+            }
+        };
+        let startup = config.startup_field && !config.startup_sent.swap(true, Ordering::Relaxed);
+        if startup {
+            serializer
+                .fields
+                .push((Cow::Borrowed("STARTUP"), "1".to_string()));
+        }
+        let suppressed = sampler.map_or(0, Sampler::take_suppressed);
+        if suppressed > 0 {
+            serializer.fields.push((
+                Cow::Borrowed("SUPPRESSED_SINCE_LAST"),
+                suppressed.to_string(),
+            ));
+        }
+
+        if config.sort_fields {
+            sort_fields(&mut serializer.fields);
+        }
+
+        if config.debug_dump {
+            let stderr = io::stderr();
+            let (fields, binary) = (&serializer.fields, &serializer.binary);
+            // Only a debugging aid, the entry is still sent if this fails.
+            let _ = native::dump(&mut stderr.lock(), priority, &message, fields, binary);
+        }
+        if config.dry_run {
+            if let Some(ref callback) = config.on_dry_run {
+                let mut fields = std::mem::take(&mut serializer.fields);
+                fields.extend(sink::binary_as_text(&serializer.binary));
+                callback(priority, &message, &fields);
+                serializer.fields = fields;
+            }
+            return Ok(());
+        }
+        let send_start = serialize_start.map(|_| config.clock.monotonic());
+        let sink = config.sink_for(priority);
+        let mut retries = 0;
+        let mut retries_field = None;
+        let sent = loop {
+            let sent = if serializer.binary.is_empty() {
+                sink.send(priority, &message, &serializer.fields)
+            } else {
+                sink.send_binary(priority, &message, &serializer.fields, &serializer.binary)
+            };
+            match sent {
+                Err(ref e) if retries < config.max_send_retries && sink::is_transient(e) => {
+                    retries += 1;
+                }
+                _ => break sent,
+            }
+            config.clock.sleep(retry_backoff(retries));
+            if config.send_retries_field {
+                let index = *retries_field.get_or_insert_with(|| {
+                    // Keep sorted fields in order
+                    let index = if config.sort_fields {
+                        serializer
+                            .fields
+                            .partition_point(|(name, _)| field_order(name, "SEND_RETRIES").is_le())
+                    } else {
+                        serializer.fields.len()
+                    };
+                    let field = (Cow::Borrowed("SEND_RETRIES"), String::new());
+                    serializer.fields.insert(index, field);
+                    index
+                });
+                serializer.fields[index].1 = retries.to_string();
+            }
+        };
+        if let Some(ref callback) = config.on_send {
+            if serializer.binary.is_empty() {
+                callback(priority, &message, &serializer.fields);
+            } else {
+                let mut fields = serializer.fields.clone();
+                fields.extend(sink::binary_as_text(&serializer.binary));
+                callback(priority, &message, &fields);
+            }
+        }
+        if let (Some(callback), Some(serialize_start), Some(send_start)) =
+            (config.on_timing.as_ref(), serialize_start, send_start)
+        {
+            callback(EntryTiming {
+                serialize: send_start.saturating_sub(serialize_start),
+                send: config.clock.monotonic().saturating_sub(send_start),
+            });
+        }
+        if let Err(e) = sent {
+            if startup {
+                // Mark the next entry instead, this one isn't in the journal.
+                config.startup_sent.store(false, Ordering::Relaxed);
+            }
+            if let Some(sampler) = sampler {
+                sampler.restore_suppressed(suppressed + 1);
+            }
+            return Err(Error::Journald(e));
+        }
+        if let Some(ref rate_limit) = config.rate_limit {
+            rate_limit.record_sent(&*config.clock);
+        }
+        Ok(())
+    }
 
-        logger_values.serialize(info, &mut serializer)?;
-        info.kv().serialize(info, &mut serializer)?;
+    /// Records below the [`min_level`](struct.JournaldDrainBuilder.html#method.min_level)
+    /// aren't enabled.
+    fn is_enabled(&self, level: Level) -> bool {
+        level.is_at_least(self.config().min_level)
+    }
+}
 
-        journal_send(
-            level_to_priority(info.level()),
-            &format!("{}", info.msg()),
-            serializer.fields.into_iter(),
-        )
-        .map_err(Error::Journald)
+/// Why `record` shouldn't be sent, if it shouldn't.
+///
+/// The checks are ordered from cheapest to the ones with side effects, so a record
+/// dropped for an earlier reason doesn't count towards sampling or the rate limit.
+fn drop_reason(config: &Config, record: &Record, priority: Priority) -> Option<DropReason> {
+    if let Some(ref switch) = config.kill_switch {
+        if switch.load(Ordering::Relaxed) {
+            return Some(DropReason::KillSwitch);
+        }
+    }
+    if !record.level().is_at_least(config.min_level) {
+        return Some(DropReason::BelowMinLevel);
+    }
+    if let Some(ref sampler) = config.samplers[u8::from(priority) as usize] {
+        if !sampler.sample() {
+            return Some(DropReason::Sampled);
+        }
+    }
+    if let (true, Some(rate_limit)) = (config.drop_rate_limited, config.rate_limit.as_ref()) {
+        if rate_limit.at_limit(&*config.clock) {
+            return Some(DropReason::RateLimited);
+        }
     }
+    None
+}
+
+/// Create a root logger sending records of at least `min_level` to journald.
+///
+/// This is a shortcut for the common combination of a `LevelFilter` and the default
+/// `JournaldDrain`, ignoring errors from sending:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// fn main() {
+///     let root = slog_journald::filtered_logger(slog::Level::Info, o!("build_id" => "12344"));
+///     debug!(root, "Not sent to journald");
+///     info!(root, "Sent to journald");
+/// }
+/// ```
+pub fn filtered_logger<T>(min_level: Level, values: OwnedKV<T>) -> Logger
+where
+    T: SendSyncRefUnwindSafeKV + 'static,
+{
+    JournaldDrain.filtered_logger(min_level, values)
+}
+
+/// Build a [`JournaldDrain`](struct.JournaldDrain.html) adding the given fields to
+/// every entry.
+///
+/// This is a shortcut for calling
+/// [`JournaldDrainBuilder::add_field`](struct.JournaldDrainBuilder.html#method.add_field)
+/// for each field. The field names are checked at compile time, so it evaluates to
+/// `Result<JournaldDrain, ConfigError>` only for the other validations of `build`:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog_journald;
+///
+/// fn main() {
+///     let tier = "backend";
+///     let drain = journald_fields! { SERVICE => "auth", TIER => tier }.unwrap();
+/// }
+/// ```
+///
+/// An invalid name fails to compile:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate slog_journald;
+///
+/// fn main() {
+///     let drain = journald_fields! { service => "auth" };
+/// }
+/// ```
+#[macro_export]
+macro_rules! journald_fields {
+    ($($name:ident => $value:expr),* $(,)*) => {{
+        $(
+            const _: () = assert!(
+                $crate::__is_valid_field_name(stringify!($name)),
+                concat!(stringify!($name), " is not a valid journald field name"),
+            );
+        )*
+        $crate::JournaldDrain::builder()
+            $(.add_field(stringify!($name), $value))*
+            .build()
+    }};
+}
+
+/// Log the `Debug` form of a value and return the value, for debugging inside expressions.
+///
+/// The value is logged as field `tap` and its source code as `tap_expr` with the given
+/// level and message. Like slog's own logging macros this needs slog's macros in scope:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// #[macro_use]
+/// extern crate slog_journald;
+///
+/// fn main() {
+///     let log = slog_journald::filtered_logger(slog::Level::Debug, o!());
+///     let doubled = journald_tap!(log, slog::Level::Debug, 1 + 2; "sum") * 2;
+///     assert_eq!(doubled, 6);
+/// }
+/// ```
+#[macro_export]
+macro_rules! journald_tap {
+    ($logger:expr, $lvl:expr, $value:expr; $($args:tt)+) => {{
+        let value = $value;
+        log!(
+            $logger, $lvl, "", $($args)+;
+            "tap" => format!("{:?}", value), "tap_expr" => stringify!($value)
+        );
+        value
+    }};
 }
 
 /// Error type for logging to journald.
@@ -77,6 +769,19 @@ pub enum Error {
     Serialization(slog::Error),
 }
 
+impl Error {
+    /// Create a representative `Error::Journald` for the OS error `errno`.
+    ///
+    /// `SdError` can't be constructed outside of libsystemd, this allows exercising
+    /// the handling of failed sends in tests.
+    pub fn test_journald(errno: i32) -> Error {
+        Error::Journald(SdError::from(format!(
+            "failed to print to journal: {}",
+            io::Error::from_raw_os_error(errno)
+        )))
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
         match *self {
@@ -101,6 +806,13 @@ impl std::error::Error for Error {
             Error::Serialization(ref e) => Some(e),
         }
     }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Journald(_) => None,
+            Error::Serialization(ref e) => Some(e),
+        }
+    }
 }
 
 impl From<slog::Error> for Error {
@@ -109,137 +821,846 @@ impl From<slog::Error> for Error {
     }
 }
 
-fn level_to_priority(level: Level) -> Priority {
-    match level {
-        Level::Critical => Priority::Critical,
-        Level::Error => Priority::Error,
-        Level::Warning => Priority::Warning,
-        Level::Info => Priority::Notice,
-        Level::Debug => Priority::Info,
-        Level::Trace => Priority::Debug,
+/// Format the message of a record, according to the configured `MessageMode`
+fn message(config: &Config, info: &Record, serializer: &mut Serializer) -> String {
+    let mut message = format_value(config, info.msg());
+    if !config.message_fields.is_empty() {
+        append_message_fields(&mut message, serializer);
     }
-}
-
-/// Journald keys must consist only of uppercase letters, numbers
-/// and underscores (but cannot begin with underscores).
-/// So we capitalize the string and replace any invalid characters with underscores
-struct SanitizedKey(Key);
-
-impl<'a> Display for SanitizedKey {
-    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
-        // Until we find a non-underscore character, we can't output underscores for any other chars
-        let mut found_non_underscore = false;
-        #[cfg_attr(not(feature = "slog/dynamic-keys"), allow(clippy::useless_asref))]
-        let key: &str = self.0.as_ref();
-        for c in key.chars() {
-            match c {
-                'A'..='Z' | '0'..='9' => {
-                    fmt.write_char(c)?;
-                    found_non_underscore = true;
-                }
-                'a'..='z' => {
-                    fmt.write_char(c.to_ascii_uppercase())?;
-                    found_non_underscore = true;
-                }
-                _ if found_non_underscore => fmt.write_char('_')?,
-                _ => {}
+    match config.message_mode {
+        MessageMode::Full => {
+            if let Some(max_len) = config.max_message_len {
+                serializer.truncated |= truncate(&mut message, max_len, &config.truncation_marker);
             }
+            message
+        }
+        MessageMode::Truncated(max_chars) => match message.char_indices().nth(max_chars) {
+            Some((end, _)) => message[..end].to_string(),
+            None => message,
+        },
+        MessageMode::MsgField => {
+            serializer.add_field(Cow::Borrowed("MSG"), message);
+            String::new()
         }
-        Ok(())
     }
 }
 
-struct Serializer {
-    fields: Vec<(Cow<'static, str>, String)>,
+/// Append the values of the `message_fields` of the config to `message`
+fn append_message_fields(message: &mut String, serializer: &mut Serializer) {
+    let config = serializer.config;
+    let mut appended = Vec::new();
+    for key in &config.message_fields {
+        for prefix in &[&config.record_prefix, &config.context_prefix] {
+            serializer.prefix = prefix;
+            let name = serializer.field_name(key);
+            let value = serializer.fields.iter().rev().find(|(k, _)| *k == name);
+            if let Some((_, value)) = value {
+                appended.push(format!("{}={}", key, value));
+                break;
+            }
+        }
+    }
+    serializer.prefix = "";
+    if !appended.is_empty() {
+        write!(message, " [{}]", appended.join(" ")).unwrap();
+    }
 }
 
-impl Serializer {
-    fn new() -> Serializer {
-        Serializer { fields: Vec::new() }
-    }
-    /// Add field without sanitizing the key
-    ///
-    /// Note: if the key isn't a valid journald key name, it will be ignored.
-    fn add_field(&mut self, key: Cow<'static, str>, value: String) {
-        self.fields.push((key, value));
+/// Format a logged value, catching panics of its `Display` implementation if enabled
+fn format_value<T: Display>(config: &Config, value: T) -> String {
+    if !config.catch_format_panics {
+        return value.to_string();
     }
+    panic::catch_unwind(AssertUnwindSafe(|| value.to_string()))
+        .unwrap_or_else(|_| "<format panicked>".to_string())
+}
 
-    #[inline]
-    #[allow(clippy::unnecessary_wraps)]
-    fn emit<T: Display>(&mut self, key: Key, val: T) -> slog::Result {
-        self.add_field(Cow::Owned(SanitizedKey(key).to_string()), val.to_string());
-        Ok(())
+/// Format a float value as configured, `None` if it isn't sent at all
+fn format_float<T: Display + fmt::LowerExp>(
+    config: &Config,
+    value: T,
+    finite: bool,
+    nan: bool,
+) -> Option<String> {
+    if !finite {
+        return match config.non_finite_floats {
+            NonFiniteFloats::Display => Some(value.to_string()),
+            NonFiniteFloats::Lowercase if nan => Some("nan".to_string()),
+            NonFiniteFloats::Lowercase => Some(value.to_string()),
+            NonFiniteFloats::Drop => None,
+        };
     }
+    Some(match config.float_format {
+        FloatFormat::Display => value.to_string(),
+        FloatFormat::Fixed(decimals) => format!("{:.*}", decimals, value),
+        FloatFormat::Scientific(decimals) => format!("{:.*e}", decimals, value),
+    })
 }
 
-macro_rules! __emitter {
-    ($name:ident : $T:ty) => {
-        fn $name(&mut self, key: Key, val: $T) -> slog::Result {
-            self.emit(key, val)
+/// Fields added by the drain that differ between entries with the same content
+const VOLATILE_FIELDS: &[&str] = &["MONOTONIC_NS", "ELAPSED_MS", "SOURCE_REALTIME_TIMESTAMP"];
+
+/// 64-bit FNV-1a hash of the message and the fields in sorted order, as hex digits
+fn content_hash(message: &str, fields: &[Field]) -> String {
+    let mut fields = fields
+        .iter()
+        .filter(|f| !VOLATILE_FIELDS.contains(&&*f.0))
+        .collect::<Vec<_>>();
+    fields.sort();
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut update = |bytes: &[u8]| {
+        // Prefix with the length, so moving bytes between parts changes the hash
+        let len = (bytes.len() as u64).to_le_bytes();
+        for &byte in len.iter().chain(bytes) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
         }
     };
-    ($name:ident = $val:expr) => {
-        fn $name(&mut self, key: Key) -> slog::Result {
-            self.emit(key, $val)
-        }
+    update(message.as_bytes());
+    for (name, value) in fields {
+        update(name.as_bytes());
+        update(value.as_bytes());
+    }
+    format!("{:016x}", hash)
+}
+
+/// Shorten `value` to at most `max_len` bytes, ending in `marker` with its first `{}`
+/// replaced by the number of bytes cut off.
+///
+/// A marker that doesn't fit into `max_len` is cut as well. Returns whether the value
+/// was truncated.
+fn truncate(value: &mut String, max_len: usize, marker: &str) -> bool {
+    if value.len() <= max_len {
+        return false;
+    }
+    let (before, after) = match marker.find("{}") {
+        Some(at) => (&marker[..at], Some(&marker[at + 2..])),
+        None => (marker, None),
     };
+    // At most the whole value is cut, so its length has enough digits for the count
+    let count_len = value.len().to_string().len();
+    let marker_len = before.len() + after.map_or(0, |after| count_len + after.len());
+    let end = floor_char_boundary(value, max_len.saturating_sub(marker_len));
+    let cut = value.len() - end;
+    value.truncate(end);
+    value.push_str(before);
+    if let Some(after) = after {
+        let _ = write!(value, "{}{}", cut, after);
+    }
+    let end = floor_char_boundary(value, max_len);
+    value.truncate(end);
+    true
 }
 
-impl slog::Serializer for Serializer {
-    __emitter!(emit_unit = "");
-    __emitter!(emit_none = "None");
+/// The largest char boundary of `value` that is at most `index`
+fn floor_char_boundary(value: &str, index: usize) -> usize {
+    let mut index = cmp::min(index, value.len());
+    while !value.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
 
-    __emitter!(emit_bool: bool);
-    __emitter!(emit_char: char);
-    __emitter!(emit_u8: u8);
-    __emitter!(emit_i8: i8);
-    __emitter!(emit_u16: u16);
-    __emitter!(emit_i16: i16);
-    __emitter!(emit_u32: u32);
-    __emitter!(emit_i32: i32);
-    __emitter!(emit_u64: u64);
-    __emitter!(emit_i64: i64);
-    __emitter!(emit_f32: f32);
-    __emitter!(emit_f64: f64);
-    __emitter!(emit_usize: usize);
-    __emitter!(emit_isize: isize);
-    __emitter!(emit_str: &str);
-    __emitter!(emit_arguments: &std::fmt::Arguments);
+/// `trace_id` as Prometheus exemplars expect it: 32 lowercase hex digits, not all zero
+fn exemplar_trace_id(trace_id: &str) -> Option<String> {
+    let valid = (trace_id.len() == 16 || trace_id.len() == 32)
+        && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+        && trace_id.bytes().any(|b| b != b'0');
+    if valid {
+        Some(format!("{:0>32}", trace_id.to_ascii_lowercase()))
+    } else {
+        None
+    }
+}
 
-    fn emit_error(&mut self, key: Key, error: &(dyn std::error::Error + 'static)) -> slog::Result {
-        #[cfg(feature = "log_errno")]
-        {
-            let mut error_source = Some(error);
-            while let Some(source) = error_source {
-                if let Some(io_error) = source.downcast_ref::<std::io::Error>() {
-                    if let Some(errno) = io_error.raw_os_error() {
-                        self.add_field(Cow::Borrowed("ERRNO"), errno.to_string());
-                    }
+/// Drop the fields of the same name as another field as `policy` says.
+fn dedup_fields(fields: &mut Vec<Field>, policy: DuplicateKeyPolicy) {
+    let keep = {
+        let mut seen = HashSet::new();
+        let mut keep = vec![true; fields.len()];
+        match policy {
+            DuplicateKeyPolicy::KeepAll => return,
+            DuplicateKeyPolicy::KeepFirst => {
+                for (keep, (name, _)) in keep.iter_mut().zip(fields.iter()) {
+                    *keep = seen.insert(&**name);
                 }
-                error_source = source.source();
             }
-        }
-        #[cfg(feature = "log_error_sources")]
-        {
-            let mut error_cause = Some(error);
-            let mut depth = 0usize;
-            while let Some(cause) = error_cause {
-                self.add_field(
-                    Cow::Owned(format!("ERROR_SOURCE_{}", depth)),
-                    cause.to_string(),
-                );
-                depth += 1;
-                error_cause = cause.cause();
+            DuplicateKeyPolicy::KeepLast => {
+                for (keep, (name, _)) in keep.iter_mut().zip(fields.iter()).rev() {
+                    *keep = seen.insert(&**name);
+                }
             }
-            self.add_field(Cow::Borrowed("ERROR_SOURCE_DEPTH"), depth.to_string());
         }
+        keep
+    };
+    let mut keep = keep.into_iter();
+    fields.retain(|_| keep.next().unwrap_or(true));
+}
 
-        self.emit_arguments(key, &format_args!("{}", ErrorAsFmt(error)))
+/// Sort `fields` by name, keeping the `CODE_*` fields first in their original order.
+fn sort_fields(fields: &mut [Field]) {
+    fields.sort_by(|(a, _), (b, _)| field_order(a, b));
+}
+
+/// The order of field names for `sort_fields`
+fn field_order(a: &str, b: &str) -> cmp::Ordering {
+    match (a.starts_with("CODE_"), b.starts_with("CODE_")) {
+        (true, true) => cmp::Ordering::Equal,
+        (true, false) => cmp::Ordering::Less,
+        (false, true) => cmp::Ordering::Greater,
+        (false, false) => a.cmp(b),
     }
 }
 
-// copied from slog
-struct ErrorAsFmt<'a>(pub &'a (dyn std::error::Error + 'static));
+/// How long to wait before the `retry`th retry of a send: 1 ms, doubling up to 32 ms
+fn retry_backoff(retry: u32) -> Duration {
+    Duration::from_millis(1 << cmp::min(retry.saturating_sub(1), 5))
+}
+
+/// The crate of a module path, i.e. its first segment
+fn crate_name(module: &str) -> &str {
+    module.split("::").next().unwrap_or(module)
+}
+
+pub(crate) fn level_to_priority(level: Level, info_as_notice: bool) -> Priority {
+    match level {
+        Level::Critical => Priority::Critical,
+        Level::Error => Priority::Error,
+        Level::Warning => Priority::Warning,
+        Level::Info if info_as_notice => Priority::Notice,
+        Level::Info => Priority::Info,
+        Level::Debug if info_as_notice => Priority::Info,
+        Level::Debug | Level::Trace => Priority::Debug,
+    }
+}
+
+/// OpenTelemetry's `SeverityNumber` of the first severity in each range
+fn otel_severity(level: Level) -> u8 {
+    match level {
+        Level::Critical => 21,
+        Level::Error => 17,
+        Level::Warning => 13,
+        Level::Info => 9,
+        Level::Debug => 5,
+        Level::Trace => 1,
+    }
+}
+
+/// Journald keys must consist only of uppercase letters, numbers
+/// and underscores (but cannot begin with underscores).
+/// So we capitalize the string and replace any invalid characters with underscores
+struct SanitizedKey<'a>(&'a str);
+
+impl<'a> Display for SanitizedKey<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        sanitize(self.0, fmt, "_")
+    }
+}
+
+/// Like `SanitizedKey`, but replacing dots with double underscores
+struct DottedKey<'a>(&'a str);
+
+impl<'a> Display for DottedKey<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        sanitize(self.0, fmt, "__")
+    }
+}
+
+fn sanitize(key: &str, fmt: &mut Formatter, dot: &str) -> std::fmt::Result {
+    // Field names have to start with a letter, so until we find one we can't output
+    // digits or underscores for any other chars
+    let mut found_letter = false;
+    for c in key.chars() {
+        match c {
+            'A'..='Z' => {
+                fmt.write_char(c)?;
+                found_letter = true;
+            }
+            'a'..='z' => {
+                fmt.write_char(c.to_ascii_uppercase())?;
+                found_letter = true;
+            }
+            '0'..='9' if found_letter => fmt.write_char(c)?,
+            '.' if found_letter => fmt.write_str(dot)?,
+            _ if found_letter => fmt.write_char('_')?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn key_str(key: &Key) -> &str {
+    // Only useless without `slog/dynamic-keys`
+    #[allow(clippy::useless_asref)]
+    key.as_ref()
+}
+
+thread_local! {
+    /// The fields of the last entry serialized on this thread, cleared, to reuse the
+    /// allocation for the next one
+    static FIELD_BUFFER: Cell<Vec<Field>> = const { Cell::new(Vec::new()) };
+}
+
+struct Serializer<'a> {
+    fields: Vec<(Cow<'static, str>, String)>,
+    config: &'a Config,
+    /// Prefix for the sanitized keys of the values currently being serialized
+    prefix: &'a str,
+    /// Fields dropped because all collision suffixes were taken
+    dropped_collisions: usize,
+    /// Fields whose sanitized key isn't a valid field name, e.g. because it's empty
+    invalid_keys: usize,
+    /// Values skipped because they failed to serialize
+    errors: usize,
+    /// Whether any value of the entry was truncated
+    truncated: bool,
+    /// Fields with binary values, sent after the text fields
+    binary: Vec<BinaryField>,
+}
+
+impl<'a> Drop for Serializer<'a> {
+    fn drop(&mut self) {
+        let mut fields = std::mem::take(&mut self.fields);
+        fields.clear();
+        // Only fails while the thread is shutting down
+        let _ = FIELD_BUFFER.try_with(|buffer| buffer.set(fields));
+    }
+}
+
+impl<'a> Serializer<'a> {
+    fn new(config: &'a Config) -> Serializer<'a> {
+        let mut fields = FIELD_BUFFER.with(Cell::take);
+        fields.reserve(config.expected_fields);
+        Serializer {
+            fields,
+            config,
+            prefix: "",
+            dropped_collisions: 0,
+            invalid_keys: 0,
+            errors: 0,
+            truncated: false,
+            binary: Vec::new(),
+        }
+    }
+    /// Add field without sanitizing the key
+    ///
+    /// Keys that aren't valid journald field names are handled as the
+    /// `InvalidKeyPolicy` says.
+    fn add_field(&mut self, key: Cow<'static, str>, mut value: String) {
+        let config = self.config;
+        let mut key = match self.checked_name(key) {
+            Some(key) => key,
+            None => return,
+        };
+        if key.is_empty() {
+            // Renamed, neither merged nor suffixed as it gets a name of its own
+            if let Some(max_len) = config.max_field_len {
+                self.truncated |= truncate(&mut value, max_len, &config.truncation_marker);
+            }
+            self.fields.push((key, value));
+            return;
+        }
+        if let Some(ref separator) = config.merge_separator {
+            if let Some(&mut (_, ref mut existing)) = self.fields.iter_mut().find(|f| f.0 == key) {
+                existing.push_str(separator);
+                existing.push_str(&value);
+                if let Some(max_len) = config.max_field_len {
+                    self.truncated |= truncate(existing, max_len, &config.truncation_marker);
+                }
+                return;
+            }
+        }
+        if let Some(max_suffix) = config.max_collision_suffix {
+            if self.has_field(&key) {
+                // Suffixes only get longer, none fit once one is too long for journald
+                let free = (2..=max_suffix)
+                    .map(|suffix| format!("{}_{}", key, suffix))
+                    .take_while(|name| builder::is_valid_field_name(name))
+                    .find(|name| !self.has_field(name));
+                match free {
+                    Some(name) => key = Cow::Owned(name),
+                    None => {
+                        self.dropped_collisions += 1;
+                        return;
+                    }
+                }
+            }
+        }
+        if let Some(max_len) = config.max_field_len {
+            self.truncated |= truncate(&mut value, max_len, &config.truncation_marker);
+        }
+        self.fields.push((key, value));
+    }
+
+    /// `name` if journald accepts it, otherwise what the `InvalidKeyPolicy` replaces it with
+    ///
+    /// Renamed fields get an empty name until `name_renamed` names them.
+    fn checked_name(&mut self, name: Cow<'static, str>) -> Option<Cow<'static, str>> {
+        if builder::is_valid_field_name(&name) {
+            return Some(name);
+        }
+        self.invalid_keys += 1;
+        match self.config.invalid_keys {
+            InvalidKeyPolicy::Count | InvalidKeyPolicy::Drop => None,
+            InvalidKeyPolicy::Rename => Some(Cow::Borrowed("")),
+        }
+    }
+
+    /// Name the renamed fields `FIELD_1`, `FIELD_2` and so on, skipping the names of
+    /// other fields, which may have been serialized after them
+    fn name_renamed(&mut self) {
+        let taken: HashSet<String> = self
+            .fields
+            .iter()
+            .map(|f| f.0.to_string())
+            .chain(self.binary.iter().map(|f| f.0.to_string()))
+            .collect();
+        let mut free = (1..)
+            .map(|n| format!("FIELD_{}", n))
+            .filter(|name| !taken.contains(name));
+        let names = self
+            .fields
+            .iter_mut()
+            .map(|f| &mut f.0)
+            .chain(self.binary.iter_mut().map(|f| &mut f.0));
+        for name in names.filter(|name| name.is_empty()) {
+            // The range is unbounded
+            *name = Cow::Owned(free.next().unwrap());
+        }
+    }
+
+    fn has_field(&self, name: &str) -> bool {
+        self.fields.iter().any(|f| f.0 == name)
+    }
+
+    #[inline]
+    #[allow(clippy::unnecessary_wraps)]
+    fn emit<T: Display>(&mut self, key: Key, val: T) -> slog::Result {
+        if key_str(&key) == self.config.message_id_key {
+            let result = self.emit_message_id(val);
+            return self.recover(result);
+        }
+        if self.is_source_timestamp_key(key_str(&key)) {
+            let result = self.emit_source_timestamp(val);
+            return self.recover(result);
+        }
+        let name = self.field_name(key_str(&key));
+        let val = format_value(self.config, val);
+        if self.is_exemplar_key(key_str(&key)) {
+            if let Some(trace_id) = exemplar_trace_id(&val) {
+                self.add_field(Cow::Borrowed("EXEMPLAR_TRACE_ID"), trace_id);
+            }
+        }
+        self.add_field(Cow::Owned(name), val);
+        Ok(())
+    }
+
+    fn is_exemplar_key(&self, key: &str) -> bool {
+        self.config.exemplar_trace_id_key.as_deref() == Some(key)
+    }
+
+    /// Count a failed value instead of failing the whole entry, if enabled
+    fn recover(&mut self, result: slog::Result) -> slog::Result {
+        match result {
+            Err(_) if self.config.skip_failed_values => {
+                self.errors += 1;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn is_source_timestamp_key(&self, key: &str) -> bool {
+        self.config.source_timestamp_key.as_deref() == Some(key)
+    }
+
+    /// Append the sanitized `key` to `name`
+    fn sanitize_into(&self, key: &str, name: &mut String) {
+        if let Some(ref sanitizer) = self.config.key_sanitizer {
+            if let Some(sanitized) = sanitizer.sanitize(key) {
+                if builder::is_valid_field_name(&sanitized) {
+                    name.push_str(&sanitized);
+                } else {
+                    let _ = write!(name, "{}", SanitizedKey(&sanitized));
+                }
+            }
+            return;
+        }
+        // Writing to a `String` can't fail
+        let _ = if self.config.dots_as_double_underscore {
+            write!(name, "{}", DottedKey(key))
+        } else {
+            write!(name, "{}", SanitizedKey(key))
+        };
+    }
+
+    /// Sanitized and prefixed field name for a key
+    fn field_name(&self, key: &str) -> String {
+        if self.config.object_fields && key.starts_with(OBJECT_KEY_PREFIX) {
+            let mut name = String::with_capacity(key.len());
+            name.push_str("OBJECT_");
+            self.sanitize_into(&key[OBJECT_KEY_PREFIX.len()..], &mut name);
+            if name.len() > "OBJECT_".len() {
+                // Journald adds the trusted fields of the process, only a checked pid may say which
+                if name == "OBJECT_PID" {
+                    name.push('_');
+                }
+                return name;
+            }
+        }
+        // Build the name in place, with room for a reserved field's underscore
+        let mut name = String::with_capacity(self.prefix.len() + key.len() + 1);
+        name.push_str(self.prefix);
+        if self.config.key_sanitizer.is_none() && builder::is_valid_field_name(key) {
+            name.push_str(key);
+        } else {
+            self.sanitize_into(key, &mut name);
+        }
+        // An empty key is an invalid field name, don't turn it into the bare prefix
+        if name.len() == self.prefix.len() {
+            name.clear();
+        }
+        let reserved = if self.config.protect_reserved_fields {
+            is_reserved_field(&name)
+        } else {
+            self.config.protect_source_fields
+                && (SOURCE_FIELDS.contains(&name.as_str())
+                    || self.config.protect_syslog_identifier && name == "SYSLOG_IDENTIFIER")
+        };
+        if reserved {
+            name.push('_');
+        }
+        name
+    }
+
+    /// Emit a number, with a `<NAME>_UNIT` companion if enabled and the key has a unit suffix
+    /// and a `<NAME>_TYPE` companion if enabled and the number is an integer of `type_name`
+    fn emit_number<T: Display>(
+        &mut self,
+        key: Key,
+        val: T,
+        type_name: Option<&'static str>,
+    ) -> slog::Result {
+        let epoch = type_name.is_some() && self.config.epoch_iso_fields;
+        let type_name = type_name.filter(|_| self.config.integer_type_fields);
+        if (!self.config.unit_fields && type_name.is_none() && !epoch)
+            || key_str(&key) == self.config.message_id_key
+            || self.is_exemplar_key(key_str(&key))
+            || self.is_source_timestamp_key(key_str(&key))
+        {
+            return self.emit(key, val);
+        }
+        let name = self.field_name(key_str(&key));
+        let val = val.to_string();
+        let unit = unit_of(&name)
+            .filter(|_| self.config.unit_fields)
+            .map(|unit| (format!("{}_UNIT", name), unit.to_string()));
+        let type_name =
+            type_name.map(|type_name| (format!("{}_TYPE", name), type_name.to_string()));
+        let iso = if epoch && EPOCH_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+            val.parse()
+                .ok()
+                .and_then(clock::rfc3339)
+                .map(|iso| (format!("{}_ISO", name), iso))
+        } else {
+            None
+        };
+        self.add_field(Cow::Owned(name), val);
+        for (companion, value) in unit.into_iter().chain(type_name).chain(iso) {
+            self.add_field(Cow::Owned(companion), value);
+        }
+        Ok(())
+    }
+
+    /// Emit a string value, trimmed if enabled
+    fn emit_text<T: Display>(&mut self, key: Key, val: T) -> slog::Result {
+        if self.config.trim_values {
+            self.emit(key, format_value(self.config, val).trim())
+        } else {
+            self.emit(key, val)
+        }
+    }
+
+    /// `MESSAGE_ID` has to be a 128-bit id, which journald expects as 32 lowercase hex digits
+    fn emit_message_id<T: Display>(&mut self, val: T) -> slog::Result {
+        let val = format_value(self.config, val);
+        let id = Id128::parse_str(&val).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid MESSAGE_ID {:?}: {}", val, e),
+            )
+        })?;
+        self.add_field(Cow::Borrowed("MESSAGE_ID"), id.lower_hex());
+        Ok(())
+    }
+
+    /// `SOURCE_REALTIME_TIMESTAMP` has to be the microseconds since the epoch
+    fn emit_source_timestamp<T: Display>(&mut self, val: T) -> slog::Result {
+        let val = format_value(self.config, val);
+        let micros: u64 = val.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid SOURCE_REALTIME_TIMESTAMP {:?}", val),
+            )
+        })?;
+        self.add_field(
+            Cow::Borrowed("SOURCE_REALTIME_TIMESTAMP"),
+            micros.to_string(),
+        );
+        Ok(())
+    }
+}
+
+/// Keys mapped to journald's `OBJECT_*` fields when enabled
+const OBJECT_KEY_PREFIX: &str = "object.";
+
+/// Fields without leading underscore that journald or this drain treat specially,
+/// see `systemd.journal-fields(7)`.
+const RESERVED_FIELDS: &[&str] = &[
+    "MESSAGE",
+    "MESSAGE_ID",
+    "PRIORITY",
+    "CODE_FILE",
+    "CODE_LINE",
+    "CODE_FUNC",
+    "CODE_FUNCTION",
+    "CODE_MODULE",
+    "ERRNO",
+    "OBJECT_PID",
+    "INVOCATION_ID",
+    "USER_INVOCATION_ID",
+    "SYSLOG_FACILITY",
+    "SYSLOG_IDENTIFIER",
+    "SYSLOG_PID",
+    "SYSLOG_TIMESTAMP",
+    "SYSLOG_RAW",
+    "DOCUMENTATION",
+    "TID",
+    "UNIT",
+    "USER_UNIT",
+    "COREDUMP_UNIT",
+    "COREDUMP_USER_UNIT",
+];
+
+/// Fields the drain sets itself, a subset of the `RESERVED_FIELDS`
+const SOURCE_FIELDS: &[&str] = &[
+    "MESSAGE",
+    "PRIORITY",
+    "MESSAGE_ID",
+    "CODE_FILE",
+    "CODE_LINE",
+    "CODE_FUNC",
+    "CODE_FUNCTION",
+    "CODE_MODULE",
+];
+
+/// Whether `name` is one of the `RESERVED_FIELDS`.
+///
+/// Field names are already uppercase, so an exact match is enough.
+fn is_reserved_field(name: &str) -> bool {
+    RESERVED_FIELDS.contains(&name)
+}
+
+/// Field name suffixes recognized as units of numeric values
+const UNIT_SUFFIXES: &[(&str, &str)] = &[
+    ("_NS", "nanoseconds"),
+    ("_US", "microseconds"),
+    ("_MS", "milliseconds"),
+    ("_SECS", "seconds"),
+    ("_BYTES", "bytes"),
+    ("_PCT", "percent"),
+];
+
+/// Suffixes of field names with seconds since the Unix epoch
+const EPOCH_SUFFIXES: &[&str] = &["_EPOCH", "_EPOCH_SECS"];
+
+fn unit_of(name: &str) -> Option<&'static str> {
+    UNIT_SUFFIXES
+        .iter()
+        .find(|&&(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix))
+        .map(|&(_, unit)| unit)
+}
+
+macro_rules! __emitter {
+    ($name:ident : $T:ty => integer) => {
+        fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+            self.emit_number(key, val, Some(stringify!($T)))
+        }
+    };
+    ($name:ident : $T:ty => float) => {
+        fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+            match format_float(self.config, val, val.is_finite(), val.is_nan()) {
+                Some(val) => self.emit_number(key, val, None),
+                None => Ok(()),
+            }
+        }
+    };
+    ($name:ident : $T:ty) => {
+        fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+            self.emit(key, val)
+        }
+    };
+    ($name:ident : $T:ty => text) => {
+        fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+            self.emit_text(key, val)
+        }
+    };
+    ($name:ident = $val:expr) => {
+        fn $name(&mut self, key: Key) -> slog::Result {
+            self.emit(key, $val)
+        }
+    };
+}
+
+impl<'a> slog::Serializer for Serializer<'a> {
+    __emitter!(emit_unit = "");
+    __emitter!(emit_none = "None");
+
+    __emitter!(emit_bool: bool);
+    __emitter!(emit_char: char);
+    __emitter!(emit_u8: u8 => integer);
+    __emitter!(emit_i8: i8 => integer);
+    __emitter!(emit_u16: u16 => integer);
+    __emitter!(emit_i16: i16 => integer);
+    __emitter!(emit_u32: u32 => integer);
+    __emitter!(emit_i32: i32 => integer);
+    __emitter!(emit_u64: u64 => integer);
+    __emitter!(emit_i64: i64 => integer);
+    __emitter!(emit_f32: f32 => float);
+    __emitter!(emit_f64: f64 => float);
+    __emitter!(emit_usize: usize => integer);
+    __emitter!(emit_isize: isize => integer);
+    __emitter!(emit_str: &str => text);
+    __emitter!(emit_arguments: &std::fmt::Arguments => text);
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        let formatted = nested::format_registered(&self.config.serde_formatters, value);
+        match formatted {
+            Ok(Some(formatted)) => return self.emit(key, formatted),
+            Ok(None) => {}
+            Err(e) => return self.recover(Err(e)),
+        }
+        match nested::flatten(key_str(&key), value, self.config.indexed_sequences) {
+            Ok(leaves) => {
+                for (path, value) in leaves {
+                    let name = self.field_name(&path);
+                    self.add_field(Cow::Owned(name), value);
+                }
+                Ok(())
+            }
+            Err(e) => self.recover(Err(e)),
+        }
+    }
+
+    /// Bytes logged as a stream, like [`BinaryValue`](struct.BinaryValue.html), are sent
+    /// as binary field, other bytes as hex like slog formats them.
+    fn emit_bytes(&mut self, key: Key, bytes: &[u8], kind: BytesKind) -> slog::Result {
+        if let BytesKind::Stream = kind {
+            let name = self.field_name(key_str(&key));
+            let name = match self.checked_name(Cow::Owned(name)) {
+                Some(name) => name,
+                None => return Ok(()),
+            };
+            let mut bytes = bytes.to_vec();
+            if let Some(max_len) = self.config.max_field_len {
+                if bytes.len() > max_len {
+                    bytes.truncate(max_len);
+                    self.truncated = true;
+                }
+            }
+            self.binary.push((name, bytes));
+            Ok(())
+        } else {
+            self.emit_arguments(key, &format_args!("{}", BytesAsFmt { bytes, kind }))
+        }
+    }
+
+    fn emit_error(&mut self, key: Key, error: &(dyn std::error::Error + 'static)) -> slog::Result {
+        #[cfg(feature = "log_errno")]
+        {
+            let mut error_source = Some(error);
+            while let Some(source) = error_source {
+                if let Some(errno) = errno_of(self.config, source) {
+                    self.add_field(Cow::Borrowed("ERRNO"), errno.to_string());
+                }
+                error_source = source.source();
+            }
+        }
+        #[cfg(feature = "log_error_sources")]
+        {
+            let mut error_cause = Some(error);
+            let mut depth = 0usize;
+            let prefix = &self.config.error_field_prefix;
+            while let Some(cause) = error_cause {
+                let name = format!("{}_SOURCE_{}", prefix, depth);
+                self.add_field(Cow::Owned(name), cause.to_string());
+                depth += 1;
+                error_cause = cause.source();
+            }
+            let name = format!("{}_SOURCE_DEPTH", prefix);
+            self.add_field(Cow::Owned(name), depth.to_string());
+        }
+
+        // Track the truncation of the error message separately from the other fields
+        let truncated = std::mem::replace(&mut self.truncated, false);
+        self.emit_arguments(key, &format_args!("{}", ErrorAsFmt(error)))?;
+        if self.truncated {
+            let name = format!("{}_TRUNCATED", self.config.error_field_prefix);
+            self.add_field(Cow::Owned(name), "1".to_string());
+        }
+        self.truncated |= truncated;
+        Ok(())
+    }
+}
+
+/// The errno of `error` itself, not of its sources
+#[cfg(feature = "log_errno")]
+fn errno_of(config: &Config, error: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    if let Some(io_error) = error.downcast_ref::<std::io::Error>() {
+        return io_error.raw_os_error();
+    }
+    #[cfg(feature = "log_errno_nix")]
+    {
+        if let Some(&errno) = error.downcast_ref::<nix::errno::Errno>() {
+            return Some(errno as i32);
+        }
+    }
+    config
+        .errno_extractors
+        .iter()
+        .find_map(|extract| extract(error))
+}
+
+// copied from slog
+pub(crate) struct BytesAsFmt<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) kind: BytesKind,
+}
+
+impl<'a> fmt::Display for BytesAsFmt<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (use_prefix, separate_with_underscore) = match self.kind {
+            BytesKind::Value => (true, true),
+            BytesKind::PlainValue => (false, false),
+            _ => (true, false),
+        };
+        if use_prefix {
+            f.write_str("0x")?;
+        }
+        for (index, byte) in self.bytes.iter().enumerate() {
+            if separate_with_underscore && index % 2 == 0 {
+                f.write_char('_')?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+// copied from slog
+struct ErrorAsFmt<'a>(pub &'a (dyn std::error::Error + 'static));
 
 impl<'a> fmt::Display for ErrorAsFmt<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -257,8 +1678,67 @@ impl<'a> fmt::Display for ErrorAsFmt<'a> {
 }
 
 #[cfg(test)]
+// `.into()` is only needed with `slog/dynamic-keys`
+#[allow(clippy::useless_conversion)]
 mod tests {
     use super::*;
+    use builder::EnvSource;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    fn fields(
+        drain: &JournaldDrain,
+        info: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Vec<(String, String)> {
+        let mut serializer = drain.serialize(info, logger_values).unwrap();
+        std::mem::take(&mut serializer.fields)
+            .into_iter()
+            .map(|(key, value)| (key.into_owned(), value))
+            .collect()
+    }
+
+    /// Fields `$drain` sends for an info record with the given values and no logger values
+    macro_rules! fields {
+        ($drain:expr, $($kv:tt)*) => {
+            fields(
+                &$drain,
+                &record!(Level::Info, "", &format_args!("msg"), b!($($kv)*)),
+                &OwnedKVList::from(o!()),
+            )
+        };
+    }
+
+    /// Priority, message and fields of a sent entry
+    type Entry = (u8, String, Vec<(String, String)>);
+    type Sent = Arc<Mutex<Vec<Entry>>>;
+
+    /// Sink keeping the entries instead of sending them
+    #[derive(Default)]
+    struct MockSink(Sent);
+
+    impl JournalSink for MockSink {
+        fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+            let fields = fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect();
+            self.0
+                .lock()
+                .unwrap()
+                .push((u8::from(priority), message.to_string(), fields));
+            Ok(())
+        }
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|&(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
 
     #[test]
     fn sanitizer_no_leading_underscores() {
@@ -266,6 +1746,14 @@ mod tests {
         assert_eq!(SanitizedKey("__A".into()).to_string(), "A");
     }
 
+    #[test]
+    fn sanitizer_no_leading_digits() {
+        assert_eq!(SanitizedKey("1abc".into()).to_string(), "ABC");
+        assert_eq!(SanitizedKey("_9x".into()).to_string(), "X");
+        assert_eq!(SanitizedKey("123".into()).to_string(), "");
+        assert_eq!(SanitizedKey("9_a1".into()).to_string(), "A1");
+    }
+
     #[test]
     fn sanitizer_allow_inner_underscore() {
         assert_eq!(SanitizedKey("A_A".into()).to_string(), "A_A");
@@ -297,4 +1785,2483 @@ mod tests {
         assert_eq!(SanitizedKey("!*".into()).to_string(), "");
         assert_eq!(SanitizedKey("(A)".into()).to_string(), "A_");
     }
+
+    #[test]
+    fn prefixes_context_and_record_keys() {
+        let drain = JournaldDrain::builder()
+            .context_prefix("CTX_")
+            .record_prefix("REC_")
+            .build()
+            .unwrap();
+        let logger_values = OwnedKVList::from(o!("key" => "context"));
+        let fields = fields(
+            &drain,
+            &record!(Level::Info, "", &format_args!("msg"), b!("key" => "record")),
+            &logger_values,
+        );
+        assert_eq!(field(&fields, "CTX_KEY"), Some("context"));
+        assert_eq!(field(&fields, "REC_KEY"), Some("record"));
+        assert_eq!(field(&fields, "KEY"), None);
+        // The drain's own fields aren't prefixed
+        assert!(field(&fields, "CODE_FILE").is_some());
+    }
+
+    #[test]
+    fn no_prefix_by_default() {
+        let logger_values = OwnedKVList::from(o!("key" => "context"));
+        let fields = fields(
+            &JournaldDrain,
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("other" => "record")
+            ),
+            &logger_values,
+        );
+        assert_eq!(field(&fields, "KEY"), Some("context"));
+        assert_eq!(field(&fields, "OTHER"), Some("record"));
+    }
+
+    #[test]
+    fn rejects_invalid_prefix() {
+        assert!(JournaldDrain::builder()
+            .record_prefix("rec_")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn message_only_skips_structured_fields() {
+        let drain = JournaldDrain::builder()
+            .message_only(true)
+            .add_field("SERVICE", "x")
+            .syslog_identifier("myservice")
+            .build()
+            .unwrap();
+        let logger_values = OwnedKVList::from(o!("key" => "context"));
+        let fields = fields(
+            &drain,
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("other" => "record")
+            ),
+            &logger_values,
+        );
+        assert!(fields.is_empty());
+    }
+
+    #[test]
+    fn message_id_from_default_key() {
+        let fields = fields(
+            &JournaldDrain,
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("message_id" => "fc2e22bc-6ee6-47b6-ac0d-54b1a1ebd2f8")
+            ),
+            &OwnedKVList::from(o!()),
+        );
+        assert_eq!(
+            field(&fields, "MESSAGE_ID"),
+            Some("fc2e22bc6ee647b6ac0d54b1a1ebd2f8")
+        );
+    }
+
+    #[test]
+    fn message_id_from_configured_key() {
+        let drain = JournaldDrain::builder()
+            .message_id_key("event_id")
+            .build()
+            .unwrap();
+        let fields = fields(
+            &drain,
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("event_id" => "FC2E22BC6EE647B6AC0D54B1A1EBD2F8", "message_id" => "zzz")
+            ),
+            &OwnedKVList::from(o!()),
+        );
+        assert_eq!(
+            field(&fields, "MESSAGE_ID"),
+            Some("fc2e22bc6ee647b6ac0d54b1a1ebd2f8")
+        );
+        assert_eq!(field(&fields, "EVENT_ID"), None);
+        // The default key is an ordinary value now, it can't add a second id
+        assert_eq!(fields.iter().filter(|(k, _)| k == "MESSAGE_ID").count(), 1);
+        assert_eq!(field(&fields, "MESSAGE_ID_"), Some("zzz"));
+    }
+
+    #[test]
+    fn message_id_rejects_malformed_id() {
+        let result = JournaldDrain.serialize(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("message_id" => "not-an-id")
+            ),
+            &OwnedKVList::from(o!()),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn estimates_rate_limited_entries() {
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .sink(sink)
+            .estimate_rate_limit(Duration::from_secs(3600), 2)
+            .build()
+            .unwrap();
+        assert_eq!(drain.rate_limited_estimate(), 0);
+        for _ in 0..5 {
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("msg"), b!()),
+                    &OwnedKVList::from(o!()),
+                )
+                .unwrap();
+        }
+        assert_eq!(sent.lock().unwrap().len(), 5);
+        assert_eq!(drain.rate_limited_estimate(), 3);
+    }
+
+    #[test]
+    fn rate_limit_window_follows_clock() {
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .sink(sink)
+            .clock(clock.clone())
+            .estimate_rate_limit(Duration::from_secs(30), 2)
+            .drop_rate_limited(true)
+            .build()
+            .unwrap();
+        let log = |drain: &JournaldDrain| {
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("msg"), b!()),
+                    &OwnedKVList::from(o!()),
+                )
+                .unwrap()
+        };
+        let burst = |drain: &JournaldDrain| {
+            for _ in 0..3 {
+                log(drain);
+            }
+        };
+        burst(&drain);
+        assert_eq!(sent.lock().unwrap().len(), 2);
+        clock.advance(Duration::from_secs(29));
+        log(&drain);
+        // Still in the first window, the entry was dropped
+        assert_eq!(sent.lock().unwrap().len(), 2);
+        clock.advance(Duration::from_secs(1));
+        burst(&drain);
+        // A new window with room for 2 entries
+        assert_eq!(sent.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn no_rate_limit_estimate_by_default() {
+        assert_eq!(JournaldDrain.rate_limited_estimate(), 0);
+    }
+
+    #[test]
+    fn unit_companion_fields() {
+        let drain = JournaldDrain::builder().unit_fields(true).build().unwrap();
+        let fields = fields!(drain, "latency_ms" => 42, "count" => 3, "name_ms" => "text");
+        assert_eq!(field(&fields, "LATENCY_MS"), Some("42"));
+        assert_eq!(field(&fields, "LATENCY_MS_UNIT"), Some("milliseconds"));
+        assert_eq!(field(&fields, "COUNT_UNIT"), None);
+        // Only numbers get a unit
+        assert_eq!(field(&fields, "NAME_MS_UNIT"), None);
+
+        let fields = fields!(JournaldDrain, "latency_ms" => 42);
+        assert_eq!(field(&fields, "LATENCY_MS_UNIT"), None);
+    }
+
+    #[cfg(feature = "tracing-integration")]
+    #[test]
+    fn current_tracing_span_fields() {
+        let subscriber = tracing_subscriber::registry();
+        tracing::subscriber::with_default(subscriber, || {
+            let fields = fields!(JournaldDrain,);
+            assert_eq!(field(&fields, "SPAN_ID"), None);
+
+            let span = tracing::info_span!("request");
+            let _entered = span.enter();
+            let fields = fields!(JournaldDrain,);
+            let id = span.id().unwrap().into_u64().to_string();
+            assert_eq!(field(&fields, "SPAN_ID"), Some(id.as_str()));
+            assert_eq!(field(&fields, "SPAN_NAME"), Some("request"));
+        });
+    }
+
+    /// Entries `builder` sends for an info record with message `msg`
+    fn sent(builder: JournaldDrainBuilder, msg: &str) -> Vec<Entry> {
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = builder.sink(sink).build().unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let sent = sent.lock().unwrap().clone();
+        sent
+    }
+
+    #[test]
+    fn message_modes() {
+        let entry = &sent(JournaldDrain::builder(), "héllo world")[0];
+        assert_eq!(entry.1, "héllo world");
+        assert_eq!(field(&entry.2, "MSG"), None);
+
+        let builder = JournaldDrain::builder().message_mode(MessageMode::Truncated(5));
+        assert_eq!(sent(builder, "héllo world")[0].1, "héllo");
+        let builder = JournaldDrain::builder().message_mode(MessageMode::Truncated(0));
+        assert_eq!(sent(builder, "héllo world")[0].1, "");
+        let builder = JournaldDrain::builder().message_mode(MessageMode::Truncated(50));
+        assert_eq!(sent(builder, "héllo world")[0].1, "héllo world");
+
+        let builder = JournaldDrain::builder().message_mode(MessageMode::MsgField);
+        let entry = &sent(builder, "héllo world")[0];
+        assert_eq!(entry.1, "");
+        assert_eq!(field(&entry.2, "MSG"), Some("héllo world"));
+        assert!(field(&entry.2, "CODE_FILE").is_some());
+    }
+
+    #[test]
+    fn test_journald_error() {
+        use std::error::Error as StdError;
+
+        let error = Error::test_journald(111);
+        match error {
+            Error::Journald(_) => {}
+            _ => panic!("expected a journald error"),
+        }
+        let message = error.to_string();
+        assert!(message.starts_with("sd_journal_sendv returned"));
+        assert!(message.contains("os error 111"));
+        assert!(error.source().is_none());
+    }
+
+    #[test]
+    fn monotonic_time_increases() {
+        let drain = JournaldDrain::builder()
+            .monotonic_time(true)
+            .build()
+            .unwrap();
+        let monotonic = |fields: &[(String, String)]| -> u128 {
+            field(fields, "MONOTONIC_NS").unwrap().parse().unwrap()
+        };
+        let first = monotonic(&fields!(drain,));
+        thread::sleep(Duration::from_millis(1));
+        let second = monotonic(&fields!(drain,));
+        assert!(second > first);
+        assert_eq!(field(&fields!(JournaldDrain,), "MONOTONIC_NS"), None);
+    }
+
+    #[cfg(feature = "nested-values")]
+    #[test]
+    fn registered_serde_formatter() {
+        #[derive(Clone, Serialize)]
+        struct Money {
+            cents: u64,
+            currency: &'static str,
+        }
+
+        let drain = JournaldDrain::builder()
+            .serde_formatter("Money", |value| {
+                format!(
+                    "{:.2} {}",
+                    value["cents"].as_u64().unwrap() as f64 / 100.0,
+                    value["currency"].as_str().unwrap()
+                )
+            })
+            .build()
+            .unwrap();
+        let price = Money {
+            cents: 1234,
+            currency: "EUR",
+        };
+        let fields = fields!(drain, "price" => slog::Serde(price));
+        assert_eq!(field(&fields, "PRICE"), Some("12.34 EUR"));
+    }
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        let mut value = "aé".to_string();
+        assert!(truncate(&mut value, 2, ""));
+        assert_eq!(value, "a");
+        let mut value = "abc".to_string();
+        assert!(!truncate(&mut value, 3, "…"));
+        assert_eq!(value, "abc");
+        let mut value = "a".repeat(100);
+        assert!(truncate(&mut value, 40, " [{} cut]"));
+        assert_eq!(value, format!("{} [70 cut]", "a".repeat(30)));
+        // Only the first `{}` is replaced
+        let mut value = "a".repeat(100);
+        assert!(truncate(&mut value, 40, "[{} of {}]"));
+        assert_eq!(value, format!("{}[71 of {{}}]", "a".repeat(29)));
+        // A marker longer than the limit is cut too
+        let mut value = "abcdef".to_string();
+        assert!(truncate(&mut value, 4, "[cut {} bytes]"));
+        assert_eq!(value, "[cut");
+        let mut value = "abcdef".to_string();
+        assert!(truncate(&mut value, 5, "…[cut]"));
+        assert_eq!(value, "…[c");
+    }
+
+    #[test]
+    fn default_length_limits() {
+        let long = "a".repeat(DEFAULT_MAX_LEN + 1);
+        let fields = fields!(JournaldDrain, "dump" => &*long);
+        let dump = field(&fields, "DUMP").unwrap();
+        assert!(dump.len() <= DEFAULT_MAX_LEN);
+        let cut = long.len() - dump.find('…').unwrap();
+        assert!(dump.ends_with(&format!("…[truncated {} bytes]", cut)));
+
+        let drain = JournaldDrain::builder()
+            .max_field_len(usize::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(
+            field(&fields!(drain, "dump" => &*long), "DUMP"),
+            Some(&*long)
+        );
+    }
+
+    #[test]
+    fn custom_truncation_marker() {
+        let builder = JournaldDrain::builder()
+            .max_field_len(12)
+            .max_message_len(10)
+            .truncation_marker("<CUT>");
+        let entry = &sent(builder, "a long message")[0];
+        assert_eq!(entry.1, "a lon<CUT>");
+        assert!(entry.2.iter().all(|(_, v)| v.len() <= 12));
+        assert!(field(&entry.2, "CODE_MODULE").unwrap().ends_with("<CUT>"));
+
+        let drain = JournaldDrain::builder().max_field_len(40).build().unwrap();
+        let fields = fields!(drain, "key" => "a value that is much too long for the limit");
+        assert_eq!(
+            field(&fields, "KEY"),
+            Some("a value that is m…[truncated 26 bytes]")
+        );
+    }
+
+    #[test]
+    fn crate_name_from_module_path() {
+        assert_eq!(crate_name("my_crate::net::http"), "my_crate");
+        assert_eq!(crate_name("my_crate"), "my_crate");
+
+        let drain = JournaldDrain::builder().crate_field(true).build().unwrap();
+        assert_eq!(field(&fields!(drain,), "CRATE"), Some("slog_journald"));
+        assert_eq!(field(&fields!(JournaldDrain,), "CRATE"), None);
+    }
+
+    #[test]
+    fn code_func() {
+        let fields = fields!(JournaldDrain,);
+        assert!(field(&fields, "CODE_FUNC").is_some());
+        assert_eq!(field(&fields, "CODE_FUNCTION"), None);
+
+        let drain = JournaldDrain::builder()
+            .code_function_field(true)
+            .build()
+            .unwrap();
+        let fields = fields!(drain,);
+        assert_eq!(field(&fields, "CODE_FUNCTION"), field(&fields, "CODE_FUNC"));
+    }
+
+    #[test]
+    fn test_mode() {
+        let drain = JournaldDrain::builder().test_mode(true).build().unwrap();
+        assert_eq!(field(&fields!(drain,), "TEST_MODE"), Some("1"));
+        let drain = JournaldDrain::builder().test_mode(false).build().unwrap();
+        assert_eq!(field(&fields!(drain,), "TEST_MODE"), None);
+    }
+
+    #[test]
+    fn version_field() {
+        let drain = JournaldDrain::builder()
+            .version_field(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            field(&fields!(drain,), "SLOG_JOURNALD_VERSION"),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        let fields = fields!(JournaldDrain,);
+        assert_eq!(field(&fields, "SLOG_JOURNALD_VERSION"), None);
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let values = |fields: &[(String, String)]| -> Vec<String> {
+            fields
+                .iter()
+                .filter(|(k, _)| k == "KEY")
+                .map(|(_, v)| v.clone())
+                .collect()
+        };
+        let fields = fields!(JournaldDrain, "key" => "a", "key" => "b");
+        assert_eq!(values(&fields), ["b", "a"]);
+
+        let drain = JournaldDrain::builder()
+            .merge_repeated_keys(",")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "key" => "a", "key" => "b");
+        assert_eq!(values(&fields), ["b,a"]);
+    }
+
+    #[test]
+    fn dry_run_skips_send() {
+        let dry_run = Sent::default();
+        let seen = dry_run.clone();
+        let builder =
+            JournaldDrain::builder()
+                .dry_run(true)
+                .on_dry_run(move |priority, message, fields| {
+                    MockSink(seen.clone())
+                        .send(priority, message, fields)
+                        .unwrap();
+                });
+        assert!(sent(builder, "not sent").is_empty());
+        let dry_run = dry_run.lock().unwrap();
+        assert_eq!(dry_run.len(), 1);
+        assert_eq!(dry_run[0].0, 5);
+        assert_eq!(dry_run[0].1, "not sent");
+        assert!(field(&dry_run[0].2, "CODE_FILE").is_some());
+    }
+
+    #[test]
+    fn routes_priorities_to_sinks() {
+        let errors = MockSink::default();
+        let sent_errors = errors.0.clone();
+        let others = MockSink::default();
+        let sent_others = others.0.clone();
+        let drain = JournaldDrain::builder()
+            .sink(others)
+            .priority_sink(Priority::Error, errors)
+            .build()
+            .unwrap();
+        let logger_values = OwnedKVList::from(o!());
+        drain
+            .log(
+                &record!(Level::Error, "", &format_args!("error"), b!()),
+                &logger_values,
+            )
+            .unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("info"), b!()),
+                &logger_values,
+            )
+            .unwrap();
+        let sent_errors = sent_errors.lock().unwrap();
+        let sent_others = sent_others.lock().unwrap();
+        assert_eq!(sent_errors.len(), 1);
+        assert_eq!(sent_errors[0].1, "error");
+        assert_eq!(sent_others.len(), 1);
+        assert_eq!(sent_others[0].1, "info");
+    }
+
+    #[test]
+    fn protects_source_fields() {
+        let fields = fields!(
+            JournaldDrain,
+            "code_file" => "a",
+            "CODE_LINE" => "b",
+            "message" => "c",
+            "syslog_identifier" => "d",
+            "MESSAGE_ID" => "garbage",
+        );
+        assert_eq!(field(&fields, "CODE_FILE_"), Some("a"));
+        assert_eq!(field(&fields, "CODE_LINE_"), Some("b"));
+        assert_eq!(field(&fields, "MESSAGE_"), Some("c"));
+        assert_eq!(field(&fields, "MESSAGE_ID_"), Some("garbage"));
+        assert_eq!(field(&fields, "MESSAGE_ID"), None);
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("d"));
+        assert_eq!(fields.iter().filter(|(k, _)| k == "CODE_FILE").count(), 1);
+        assert!(field(&fields, "CODE_FILE").unwrap().ends_with(".rs"));
+
+        let drain = JournaldDrain::builder()
+            .protect_source_fields(false)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "code_file" => "a");
+        assert_eq!(fields.iter().filter(|(k, _)| k == "CODE_FILE").count(), 2);
+    }
+
+    #[test]
+    fn protects_reserved_fields() {
+        let drain = JournaldDrain::builder()
+            .protect_reserved_fields(true)
+            .build()
+            .unwrap();
+        let fields = fields!(
+            drain,
+            "message" => "a",
+            "Priority" => "b",
+            "code_file" => "c",
+            "syslog_identifier" => "d",
+            "errno" => "e",
+            "tid" => "f",
+            "object_pid" => "g",
+        );
+        assert_eq!(field(&fields, "MESSAGE_"), Some("a"));
+        assert_eq!(field(&fields, "PRIORITY_"), Some("b"));
+        assert_eq!(field(&fields, "CODE_FILE_"), Some("c"));
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER_"), Some("d"));
+        assert_eq!(field(&fields, "ERRNO_"), Some("e"));
+        assert_eq!(field(&fields, "TID_"), Some("f"));
+        assert_eq!(field(&fields, "OBJECT_PID_"), Some("g"));
+        // The drain's own field is untouched
+        assert_ne!(field(&fields, "CODE_FILE"), Some("c"));
+        assert_eq!(fields.iter().filter(|(k, _)| k == "CODE_FILE").count(), 1);
+
+        // Journald's address fields lose their underscores and aren't special anymore
+        let fields = fields!(drain, "__CURSOR" => "a", "__REALTIME_TIMESTAMP" => "b");
+        assert_eq!(field(&fields, "CURSOR"), Some("a"));
+        assert_eq!(field(&fields, "REALTIME_TIMESTAMP"), Some("b"));
+
+        let fields = fields!(JournaldDrain, "syslog_identifier" => "d");
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("d"));
+    }
+
+    #[test]
+    fn object_fields() {
+        let drain = JournaldDrain::builder()
+            .object_fields(true)
+            .record_prefix("APP_")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "object.uid" => 1000, "object.name" => "alice", "uid" => 0);
+        assert_eq!(field(&fields, "OBJECT_UID"), Some("1000"));
+        assert_eq!(field(&fields, "OBJECT_NAME"), Some("alice"));
+        assert_eq!(field(&fields, "APP_UID"), Some("0"));
+
+        let fields = fields!(drain, "object.pid" => "notapid");
+        assert_eq!(field(&fields, "OBJECT_PID"), None);
+        assert_eq!(field(&fields, "OBJECT_PID_"), Some("notapid"));
+
+        let drain = JournaldDrain::builder()
+            .record_prefix("APP_")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "object.uid" => 1000);
+        assert_eq!(field(&fields, "APP_OBJECT_UID"), Some("1000"));
+    }
+
+    #[test]
+    fn filtered_logger_drops_lower_levels() {
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = JournaldDrain::builder().sink(sink).build().unwrap();
+        let logger = drain.filtered_logger(Level::Warning, o!());
+        info!(logger, "info");
+        warn!(logger, "warning");
+        error!(logger, "error");
+        let messages: Vec<String> = sent.lock().unwrap().iter().map(|e| e.1.clone()).collect();
+        assert_eq!(messages, ["warning", "error"]);
+    }
+
+    #[cfg(feature = "nested-values")]
+    #[test]
+    fn flattens_enum_variants() {
+        #[derive(Clone, Serialize)]
+        struct Status {
+            state: State,
+        }
+
+        #[derive(Clone, Serialize)]
+        enum State {
+            Running,
+            Waiting(u32),
+            Failed { code: i32, reason: &'static str },
+            Moved(u32, u32),
+        }
+
+        let status = |state| slog::Serde(Status { state });
+        let fields = fields!(JournaldDrain, "status" => status(State::Running));
+        assert_eq!(field(&fields, "STATUS_STATE"), Some("Running"));
+
+        let fields = fields!(JournaldDrain, "status" => status(State::Waiting(3)));
+        assert_eq!(field(&fields, "STATUS_STATE"), Some("Waiting"));
+        assert_eq!(field(&fields, "STATUS_STATE_WAITING"), Some("3"));
+
+        let failed = State::Failed {
+            code: 2,
+            reason: "timeout",
+        };
+        let fields = fields!(JournaldDrain, "state" => slog::Serde(failed));
+        assert_eq!(field(&fields, "STATE"), Some("Failed"));
+        assert_eq!(field(&fields, "STATE_FAILED_CODE"), Some("2"));
+        assert_eq!(field(&fields, "STATE_FAILED_REASON"), Some("timeout"));
+
+        let fields = fields!(JournaldDrain, "state" => slog::Serde(State::Moved(1, 2)));
+        assert_eq!(field(&fields, "STATE"), Some("Moved"));
+        assert_eq!(field(&fields, "STATE_MOVED_0"), Some("1"));
+        assert_eq!(field(&fields, "STATE_MOVED_1"), Some("2"));
+    }
+
+    #[cfg(feature = "nested-values")]
+    #[test]
+    fn indexed_sequences() {
+        #[derive(Clone, Serialize)]
+        struct Request {
+            tags: Vec<&'static str>,
+            hops: Vec<(u32, &'static str)>,
+            empty: Vec<u32>,
+        }
+
+        let request = slog::Serde(Request {
+            tags: vec!["a", "b"],
+            hops: vec![(1, "x")],
+            empty: vec![],
+        });
+        let fields = fields!(JournaldDrain, "ctx" => request.clone());
+        assert_eq!(field(&fields, "CTX_TAGS_0"), Some("a"));
+        assert_eq!(field(&fields, "CTX_TAGS_1"), Some("b"));
+        assert_eq!(field(&fields, "CTX_HOPS_0_0"), Some("1"));
+        assert_eq!(field(&fields, "CTX_HOPS_0_1"), Some("x"));
+        assert!(fields.iter().all(|(k, _)| !k.starts_with("CTX_EMPTY")));
+
+        let drain = JournaldDrain::builder()
+            .indexed_sequences(false)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "ctx" => request);
+        assert_eq!(field(&fields, "CTX_TAGS"), Some(r#"["a","b"]"#));
+    }
+
+    #[test]
+    fn startup_field_only_on_first_entry() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .startup_field(true)
+            .sink(sink)
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("x"), b!()),
+                    &OwnedKVList::from(o!()),
+                )
+                .unwrap();
+        }
+        let entries = entries.lock().unwrap();
+        assert_eq!(field(&entries[0].2, "STARTUP"), Some("1"));
+        assert_eq!(field(&entries[1].2, "STARTUP"), None);
+        assert_eq!(field(&entries[2].2, "STARTUP"), None);
+
+        assert_eq!(
+            field(&sent(JournaldDrain::builder(), "x")[0].2, "STARTUP"),
+            None
+        );
+    }
+
+    #[test]
+    fn info_as_notice() {
+        let priorities = |info_as_notice| {
+            let levels = [Level::Warning, Level::Info, Level::Debug, Level::Trace];
+            levels
+                .iter()
+                .map(|&level| u8::from(level_to_priority(level, info_as_notice)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(priorities(true), vec![4, 5, 6, 7]);
+        assert_eq!(priorities(false), vec![4, 6, 7, 7]);
+
+        let builder = JournaldDrain::builder().info_as_notice(false);
+        assert_eq!(sent(builder, "info")[0].0, 6);
+        assert_eq!(sent(JournaldDrain::builder(), "info")[0].0, 5);
+    }
+
+    #[test]
+    fn sampling_reports_suppressed() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .sample(Priority::Info, 3)
+            .sink(sink)
+            .build()
+            .unwrap();
+        for i in 0..8 {
+            let values = OwnedKVList::from(o!());
+            if i == 4 {
+                drain.log(
+                    &record!(Level::Error, "", &format_args!("{}", i), b!()),
+                    &values,
+                )
+            } else {
+                drain.log(
+                    &record!(Level::Debug, "", &format_args!("{}", i), b!()),
+                    &values,
+                )
+            }
+            .unwrap();
+        }
+        let entries = entries.lock().unwrap();
+        let sent = entries
+            .iter()
+            .map(|e| (e.1.as_str(), field(&e.2, "SUPPRESSED_SINCE_LAST")))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![("0", None), ("3", Some("2")), ("4", None), ("7", Some("2"))]
+        );
+
+        assert!(JournaldDrain::builder()
+            .sample(Priority::Info, 0)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn sampling_counts_entries_that_failed() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .sample(Priority::Info, 2)
+            .sink(sink)
+            .build()
+            .unwrap();
+        let values = OwnedKVList::from(o!());
+        let bad_id = drain.log(
+            &record!(
+                Level::Debug,
+                "",
+                &format_args!("0"),
+                b!("message_id" => "bad")
+            ),
+            &values,
+        );
+        assert!(bad_id.is_err());
+        for i in 1..3 {
+            drain
+                .log(
+                    &record!(Level::Debug, "", &format_args!("{}", i), b!()),
+                    &values,
+                )
+                .unwrap();
+        }
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1, "2");
+        assert_eq!(field(&entries[0].2, "SUPPRESSED_SINCE_LAST"), Some("2"));
+    }
+
+    #[test]
+    fn integer_type_fields() {
+        let drain = JournaldDrain::builder()
+            .integer_type_fields(true)
+            .build()
+            .unwrap();
+        let fields =
+            fields!(drain, "count" => 3u64, "delta" => -2i8, "len" => 4usize, "ratio" => 0.5);
+        assert_eq!(field(&fields, "COUNT_TYPE"), Some("u64"));
+        assert_eq!(field(&fields, "DELTA"), Some("-2"));
+        assert_eq!(field(&fields, "DELTA_TYPE"), Some("i8"));
+        assert_eq!(field(&fields, "LEN_TYPE"), Some("usize"));
+        assert_eq!(field(&fields, "RATIO_TYPE"), None);
+
+        let fields = fields!(JournaldDrain, "count" => 3u64);
+        assert_eq!(field(&fields, "COUNT_TYPE"), None);
+    }
+
+    #[test]
+    fn trim_values() {
+        let drain = JournaldDrain::builder().trim_values(true).build().unwrap();
+        let fields = fields!(drain, "user" => "  padded  ", "args" => format_args!(" {} ", 1));
+        assert_eq!(field(&fields, "USER"), Some("padded"));
+        assert_eq!(field(&fields, "ARGS"), Some("1"));
+
+        let fields = fields!(JournaldDrain, "user" => "  padded  ");
+        assert_eq!(field(&fields, "USER"), Some("  padded  "));
+    }
+
+    #[test]
+    fn elapsed_field_increases() {
+        let drain = JournaldDrain::builder()
+            .elapsed_field(true)
+            .build()
+            .unwrap();
+        let elapsed = |fields: &[(String, String)]| -> u128 {
+            field(fields, "ELAPSED_MS").unwrap().parse().unwrap()
+        };
+        let first = elapsed(&fields!(drain,));
+        thread::sleep(Duration::from_millis(5));
+        let second = elapsed(&fields!(drain,));
+        assert!(second >= first + 5, "{} then {}", first, second);
+        assert_eq!(field(&fields!(JournaldDrain,), "ELAPSED_MS"), None);
+    }
+
+    #[test]
+    fn timestamps_from_clock() {
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        clock.advance(Duration::from_secs(10));
+        let drain = JournaldDrain::builder()
+            .clock(clock.clone())
+            .monotonic_time(true)
+            .elapsed_field(true)
+            .build()
+            .unwrap();
+        clock.advance(Duration::from_millis(1500));
+        let fields = fields!(drain,);
+        assert_eq!(field(&fields, "MONOTONIC_NS"), Some("1500000000"));
+        assert_eq!(field(&fields, "ELAPSED_MS"), Some("1500"));
+    }
+
+    #[test]
+    fn invocation_id_field() {
+        let id = "0123456789abcdef0123456789abcdef";
+        let env: EnvSource = |name| match name {
+            "INVOCATION_ID" => Some("0123456789abcdef0123456789abcdef".to_string()),
+            _ => None,
+        };
+        let drain = JournaldDrain::builder()
+            .env(env)
+            .invocation_id_field(true)
+            .build()
+            .unwrap();
+        let without = JournaldDrain::builder().env(env).build().unwrap();
+        assert_eq!(field(&fields!(drain,), "APP_INVOCATION_ID"), Some(id));
+        assert_eq!(field(&fields!(without,), "APP_INVOCATION_ID"), None);
+
+        let missing = JournaldDrain::builder()
+            .env(|_| None)
+            .invocation_id_field(true)
+            .build()
+            .unwrap();
+        assert_eq!(field(&fields!(missing,), "APP_INVOCATION_ID"), None);
+    }
+
+    #[test]
+    fn static_fields() {
+        let drain = JournaldDrain::builder()
+            .add_field("SERVICE", "myservice")
+            .add_field("CONTAINER_NAME", String::from("web-1"))
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "key" => "value");
+        assert_eq!(fields[0], ("SERVICE".to_string(), "myservice".to_string()));
+        assert_eq!(
+            fields[1],
+            ("CONTAINER_NAME".to_string(), "web-1".to_string())
+        );
+        assert_eq!(field(&fields, "KEY"), Some("value"));
+
+        for name in &[
+            "lower", "_TRUSTED", "1ST", "", "DASH-ED", "PRIORITY", "MESSAGE",
+        ] {
+            let built = JournaldDrain::builder().add_field(*name, "x").build();
+            assert!(built.is_err(), "{:?}", name);
+        }
+        let long = "A".repeat(65);
+        assert!(JournaldDrain::builder()
+            .add_field(long, "x")
+            .build()
+            .is_err());
+        // Only set by the drain itself or validated with `WellKnownFields`
+        for name in &[
+            "MESSAGE_ID",
+            "CODE_FILE",
+            "CODE_LINE",
+            "SYSLOG_IDENTIFIER",
+            "OBJECT_PID",
+        ] {
+            let built = JournaldDrain::builder().add_field(*name, "x").build();
+            assert!(built.is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn well_known_fields() {
+        let id = MessageId::parse("fc2e22bc6ee647b6b90729ab34a250b1").unwrap();
+        let well_known = WellKnownFields::new()
+            .message_id(id)
+            .syslog_identifier("myservice")
+            .object_pid(42);
+        let drain = JournaldDrain::builder()
+            .well_known_fields(well_known)
+            .build()
+            .unwrap();
+        let fields = fields!(drain,);
+        let id = "fc2e22bc6ee647b6b90729ab34a250b1";
+        assert_eq!(field(&fields, "MESSAGE_ID"), Some(id));
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("myservice"));
+        assert_eq!(field(&fields, "OBJECT_PID"), Some("42"));
+
+        let invalid = WellKnownFields::new().syslog_facility(42);
+        let built = JournaldDrain::builder().well_known_fields(invalid).build();
+        assert!(built.is_err());
+    }
+
+    #[test]
+    fn journald_fields_macro() {
+        let tier = String::from("backend");
+        let drain = journald_fields! { SERVICE => "auth", TIER => tier }.unwrap();
+        let fields = fields!(drain,);
+        assert_eq!(field(&fields, "SERVICE"), Some("auth"));
+        assert_eq!(field(&fields, "TIER"), Some("backend"));
+    }
+
+    #[test]
+    fn collision_suffixes() {
+        let drain = JournaldDrain::builder()
+            .disambiguate_collisions(3)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "a.b" => 1, "a_b" => 2, "A-B" => 3, "a b" => 4, "a!b" => 5);
+        // slog serializes the values of a record last to first
+        assert_eq!(field(&fields, "A_B"), Some("5"));
+        assert_eq!(field(&fields, "A_B_2"), Some("4"));
+        assert_eq!(field(&fields, "A_B_3"), Some("3"));
+        assert_eq!(fields.iter().filter(|f| f.0.starts_with("A_B")).count(), 3);
+        assert_eq!(field(&fields, "COLLISIONS_DROPPED"), Some("2"));
+
+        // 63 bytes, there's no room for a suffix
+        const LONG: &str = "kkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkk";
+        let fields = fields!(drain, LONG => 1, LONG => 2);
+        assert_eq!(field(&fields, &LONG.to_uppercase()), Some("2"));
+        assert!(fields.iter().all(|(k, _)| builder::is_valid_field_name(k)));
+        assert_eq!(field(&fields, "COLLISIONS_DROPPED"), Some("1"));
+
+        let fields = fields!(JournaldDrain, "a.b" => 1, "a_b" => 2);
+        assert_eq!(fields.iter().filter(|f| f.0 == "A_B").count(), 2);
+        assert_eq!(field(&fields, "COLLISIONS_DROPPED"), None);
+    }
+
+    #[test]
+    fn journald_tap_passes_value_through() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder().sink(sink).build().unwrap();
+        let log = Logger::root(drain.ignore_res(), o!());
+        let doubled = journald_tap!(log, Level::Debug, vec![1, 2]; "before {}", "doubling")
+            .iter()
+            .map(|x| x * 2)
+            .collect::<Vec<_>>();
+        assert_eq!(doubled, vec![2, 4]);
+
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries[0].1, "before doubling");
+        assert_eq!(field(&entries[0].2, "TAP"), Some("[1, 2]"));
+        assert_eq!(field(&entries[0].2, "TAP_EXPR"), Some("vec![1, 2]"));
+    }
+
+    #[test]
+    fn truncated_flag() {
+        let builder = || {
+            JournaldDrain::builder()
+                .max_field_len(64)
+                .max_message_len(8)
+        };
+        let short = sent(builder(), "short");
+        assert_eq!(field(&short[0].2, "TRUNCATED"), None);
+        let long_message = sent(builder(), "much too long");
+        assert_eq!(field(&long_message[0].2, "TRUNCATED"), Some("1"));
+
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = builder().sink(sink).build().unwrap();
+        let long = "x".repeat(100);
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("msg"),
+                    b!("a" => &long, "b" => &long)
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        assert_eq!(
+            entries[0].2.iter().filter(|f| f.0 == "TRUNCATED").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn cmdline_field() {
+        let drain = JournaldDrain::builder()
+            .cmdline_field(true)
+            .build()
+            .unwrap();
+        let args = std::env::args().collect::<Vec<_>>().join(" ");
+        assert_eq!(field(&fields!(drain,), "APP_CMDLINE"), Some(&*args));
+        assert_eq!(field(&fields!(JournaldDrain,), "APP_CMDLINE"), None);
+
+        let drain = JournaldDrain::builder()
+            .cmdline_field(true)
+            .max_field_len(3)
+            .truncation_marker("")
+            .build()
+            .unwrap();
+        assert_eq!(field(&fields!(drain,), "APP_CMDLINE"), Some(&args[..3]));
+    }
+
+    #[test]
+    fn static_fields_as_defaults() {
+        let builder = || {
+            JournaldDrain::builder()
+                .add_field("COMPONENT", "core")
+                .add_field("TIER", "backend")
+        };
+        let drain = builder().static_fields_as_defaults(true).build().unwrap();
+        let fields = fields!(drain, "component" => "db");
+        assert_eq!(fields.iter().filter(|f| f.0 == "COMPONENT").count(), 1);
+        assert_eq!(field(&fields, "COMPONENT"), Some("db"));
+        assert_eq!(field(&fields, "TIER"), Some("backend"));
+
+        let drain = builder().build().unwrap();
+        let fields = fields!(drain, "component" => "db");
+        assert_eq!(fields.iter().filter(|f| f.0 == "COMPONENT").count(), 2);
+    }
+
+    #[test]
+    fn exemplar_trace_id() {
+        let drain = JournaldDrain::builder()
+            .exemplar_trace_id_key("trace_id")
+            .build()
+            .unwrap();
+        let trace_id = "4BF92F3577B34DA6A3CE929D0E0E4736";
+        let fields = fields!(drain, "trace_id" => trace_id);
+        assert_eq!(
+            field(&fields, "EXEMPLAR_TRACE_ID"),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(field(&fields, "TRACE_ID"), Some(trace_id));
+
+        let fields = fields!(drain, "trace_id" => "a3ce929d0e0e4736");
+        assert_eq!(
+            field(&fields, "EXEMPLAR_TRACE_ID"),
+            Some("0000000000000000a3ce929d0e0e4736")
+        );
+        for invalid in &["", "xyz", "0000000000000000", "a3ce929d0e0e47361"] {
+            let fields = fields!(drain, "trace_id" => *invalid);
+            assert_eq!(field(&fields, "EXEMPLAR_TRACE_ID"), None, "{:?}", invalid);
+        }
+        assert_eq!(
+            field(
+                &fields!(JournaldDrain, "trace_id" => trace_id),
+                "EXEMPLAR_TRACE_ID"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn catches_format_panics() {
+        struct Panics;
+
+        impl Display for Panics {
+            fn fmt(&self, _: &mut Formatter) -> fmt::Result {
+                panic!("broken Display")
+            }
+        }
+
+        let entry = &sent(JournaldDrain::builder().catch_format_panics(true), "ok")[0];
+        assert_eq!(entry.1, "ok");
+
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .catch_format_panics(true)
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("{}", Panics),
+                    b!("value" => format_args!("{}", Panics))
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries[0].1, "<format panicked>");
+        assert_eq!(field(&entries[0].2, "VALUE"), Some("<format panicked>"));
+    }
+
+    #[test]
+    fn path_values() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::path::Path;
+
+        let fields = fields!(JournaldDrain, "path" => PathValue("/var//log/./app/"));
+        assert_eq!(field(&fields, "PATH"), Some("/var/log/app"));
+        let non_utf8 = Path::new(OsStr::from_bytes(b"/tmp/caf\xe9"));
+        let serializer = JournaldDrain
+            .serialize(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("msg"),
+                    b!("path" => PathValue(non_utf8))
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        assert_eq!(
+            serializer.binary,
+            vec![(Cow::Borrowed("PATH"), b"/tmp/caf\xe9".to_vec())]
+        );
+    }
+
+    /// Sink keeping the binary fields of the entries
+    #[derive(Default)]
+    struct BinarySink(Arc<Mutex<Vec<Vec<BinaryField>>>>);
+
+    impl JournalSink for BinarySink {
+        fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+            self.0.lock().unwrap().push(Vec::new());
+            Ok(())
+        }
+
+        fn send_binary(
+            &self,
+            _: Priority,
+            _: &str,
+            _: &[Field],
+            binary: &[BinaryField],
+        ) -> Result<(), SdError> {
+            self.0.lock().unwrap().push(binary.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn binary_values() {
+        struct Hash;
+        impl slog::Value for Hash {
+            fn serialize(
+                &self,
+                _: &Record,
+                key: Key,
+                serializer: &mut dyn slog::Serializer,
+            ) -> slog::Result {
+                serializer.emit_bytes(key, &[0xab, 0xcd], BytesKind::Value)
+            }
+        }
+
+        let sink = BinarySink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .max_field_len(3)
+            .sink(sink)
+            .build()
+            .unwrap();
+        let payload = [0u8, 0xff, b'\n', b'x'];
+        let values = OwnedKVList::from(o!());
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("a"),
+                    b!("payload" => BinaryValue(&payload[..3]))
+                ),
+                &values,
+            )
+            .unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("b"),
+                    b!("payload" => BinaryValue(&payload[..]))
+                ),
+                &values,
+            )
+            .unwrap();
+        drain
+            .log(&record!(Level::Info, "", &format_args!("c"), b!()), &values)
+            .unwrap();
+        assert_eq!(
+            *entries.lock().unwrap(),
+            vec![
+                vec![(Cow::Borrowed("PAYLOAD"), vec![0, 0xff, b'\n'])],
+                vec![(Cow::Borrowed("PAYLOAD"), vec![0, 0xff, b'\n'])],
+                vec![],
+            ]
+        );
+
+        // Other bytes stay text and sinks without binary support get hex
+        let fields = fields!(JournaldDrain, "hash" => Hash);
+        assert_eq!(field(&fields, "HASH"), Some("0x_ABCD"));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let drain = {
+            let sent = sent.clone();
+            JournaldDrain::builder()
+                .dry_run(true)
+                .on_dry_run(move |_, _, fields| sent.lock().unwrap().extend(fields.to_vec()))
+                .build()
+                .unwrap()
+        };
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("a"),
+                    b!("payload" => BinaryValue(&payload[..2]))
+                ),
+                &values,
+            )
+            .unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            sent.last().unwrap(),
+            &(Cow::Borrowed("PAYLOAD"), "0x00FF".to_string())
+        );
+    }
+
+    #[test]
+    fn content_hash_ignores_field_order() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .content_hash(true)
+            .monotonic_time(true)
+            .sink(sink)
+            .build()
+            .unwrap();
+        let logger_values = [
+            OwnedKVList::from(o!("a" => 1, "b" => 2)),
+            OwnedKVList::from(o!("b" => 2, "a" => 1)),
+            OwnedKVList::from(o!("a" => 1, "b" => 3)),
+        ];
+        for values in &logger_values {
+            thread::sleep(Duration::from_millis(1));
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("same"), b!("c" => 3)),
+                    values,
+                )
+                .unwrap();
+        }
+        let entries = entries.lock().unwrap();
+        let hashes = entries
+            .iter()
+            .map(|e| field(&e.2, "CONTENT_HASH").unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(hashes[0].len(), 16);
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
+    /// Error with a chain of `depth` sources
+    #[derive(Debug)]
+    struct Chain(usize, Option<Box<Chain>>);
+
+    impl Chain {
+        fn new(depth: usize) -> Chain {
+            let source = if depth == 0 {
+                None
+            } else {
+                Some(Box::new(Chain::new(depth - 1)))
+            };
+            Chain(depth, source)
+        }
+    }
+
+    impl fmt::Display for Chain {
+        fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+            write!(f, "step {} failed", self.0)
+        }
+    }
+
+    impl std::error::Error for Chain {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1
+                .as_ref()
+                .map(|e| &**e as &(dyn std::error::Error + 'static))
+        }
+    }
+
+    #[test]
+    fn error_truncated() {
+        let error = Chain::new(30);
+        let drain = JournaldDrain::builder()
+            .max_field_len(64)
+            .truncation_marker("...")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "err" => &error as &(dyn std::error::Error + 'static));
+        let err = field(&fields, "ERR").unwrap();
+        assert_eq!(err.len(), 64);
+        assert!(err.starts_with("step 30 failed: step 29 failed: "));
+        assert!(err.ends_with("..."));
+        assert_eq!(field(&fields, "ERROR_TRUNCATED"), Some("1"));
+
+        let short = Chain::new(1);
+        let fields = fields!(drain, "err" => &short as &(dyn std::error::Error + 'static));
+        assert_eq!(field(&fields, "ERR"), Some("step 1 failed: step 0 failed"));
+        assert_eq!(field(&fields, "ERROR_TRUNCATED"), None);
+    }
+
+    #[cfg(feature = "log_error_sources")]
+    #[test]
+    fn error_field_prefix() {
+        let error = io::Error::other("disk full");
+        let drain = JournaldDrain::builder()
+            .error_field_prefix("SLOG_ERROR")
+            .build()
+            .unwrap();
+        let fields = fields!(
+            drain,
+            "error_source_0" => "user value",
+            "err" => &error as &(dyn std::error::Error + 'static)
+        );
+        assert_eq!(field(&fields, "ERROR_SOURCE_0"), Some("user value"));
+        assert_eq!(fields.iter().filter(|f| f.0 == "ERROR_SOURCE_0").count(), 1);
+        assert_eq!(field(&fields, "SLOG_ERROR_SOURCE_0"), Some("disk full"));
+        assert_eq!(field(&fields, "SLOG_ERROR_SOURCE_DEPTH"), Some("1"));
+
+        let fields = fields!(JournaldDrain, "err" => &error as &(dyn std::error::Error + 'static));
+        assert_eq!(field(&fields, "ERROR_SOURCE_0"), Some("disk full"));
+
+        for invalid in &["", "lower", "_X"] {
+            assert!(JournaldDrain::builder()
+                .error_field_prefix(*invalid)
+                .build()
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn init_entry_on_build() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .init_entry(true)
+            .add_field("SERVICE", "auth")
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("after"), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 6);
+        assert_eq!(field(&entries[0].2, "DRAIN_INIT"), Some("1"));
+        assert_eq!(
+            field(&entries[0].2, "DRAIN_VERSION"),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(field(&entries[0].2, "SERVICE"), Some("auth"));
+        assert_eq!(field(&entries[1].2, "DRAIN_INIT"), None);
+
+        struct Unreachable;
+
+        impl JournalSink for Unreachable {
+            fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+                match Error::test_journald(111) {
+                    Error::Journald(e) => Err(e),
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        let built = JournaldDrain::builder()
+            .init_entry(true)
+            .sink(Unreachable)
+            .build();
+        assert!(built.is_err());
+    }
+
+    #[test]
+    fn dots_as_double_underscore() {
+        let drain = JournaldDrain::builder()
+            .dots_as_double_underscore(true)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "a.b.c" => 1, "http-request.method" => 2, ".x" => 3);
+        assert_eq!(field(&fields, "A__B__C"), Some("1"));
+        assert_eq!(field(&fields, "HTTP_REQUEST__METHOD"), Some("2"));
+        assert_eq!(field(&fields, "X"), Some("3"));
+        assert_eq!(
+            field(&fields!(JournaldDrain, "a.b.c" => 1), "A_B_C"),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn timing_hook() {
+        struct AdvancesClock(ManualClock, Duration);
+
+        impl slog::Value for AdvancesClock {
+            fn serialize(
+                &self,
+                _: &Record,
+                key: Key,
+                serializer: &mut dyn slog::Serializer,
+            ) -> slog::Result {
+                self.0.advance(self.1);
+                serializer.emit_unit(key)
+            }
+        }
+
+        impl JournalSink for AdvancesClock {
+            fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+                self.0.advance(self.1);
+                Ok(())
+            }
+        }
+
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        let timings = Arc::new(Mutex::new(Vec::new()));
+        let seen = timings.clone();
+        let drain = JournaldDrain::builder()
+            .clock(clock.clone())
+            .sink(AdvancesClock(clock.clone(), Duration::from_millis(3)))
+            .on_timing(move |timing| seen.lock().unwrap().push(timing))
+            .build()
+            .unwrap();
+        let slow = AdvancesClock(clock, Duration::from_millis(2));
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("x"), b!("slow" => slow)),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let timings = timings.lock().unwrap();
+        assert_eq!(
+            *timings,
+            vec![EntryTiming {
+                serialize: Duration::from_millis(2),
+                send: Duration::from_millis(3),
+            }]
+        );
+    }
+
+    #[test]
+    fn retries_transient_failures() {
+        struct Flaky {
+            failures: Mutex<Vec<i32>>,
+            attempts: Sent,
+        }
+
+        impl JournalSink for Flaky {
+            fn send(
+                &self,
+                priority: Priority,
+                message: &str,
+                fields: &[Field],
+            ) -> Result<(), SdError> {
+                MockSink(self.attempts.clone()).send(priority, message, fields)?;
+                match self.failures.lock().unwrap().pop() {
+                    Some(errno) => match Error::test_journald(errno) {
+                        Error::Journald(e) => Err(e),
+                        _ => unreachable!(),
+                    },
+                    None => Ok(()),
+                }
+            }
+        }
+
+        let attempts = Sent::default();
+        let sink = Flaky {
+            failures: Mutex::new(vec![11, 105]),
+            attempts: attempts.clone(),
+        };
+        let clock = ManualClock::new(std::time::UNIX_EPOCH);
+        let drain = JournaldDrain::builder()
+            .retry_sends(3)
+            .send_retries_field(true)
+            .sort_fields(true)
+            .clock(clock.clone())
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("x"), b!("zone" => "eu")),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 3);
+        assert_eq!(field(&attempts[0].2, "SEND_RETRIES"), None);
+        assert_eq!(field(&attempts[1].2, "SEND_RETRIES"), Some("1"));
+        assert_eq!(field(&attempts[2].2, "SEND_RETRIES"), Some("2"));
+        assert_eq!(
+            attempts[2]
+                .2
+                .iter()
+                .filter(|f| f.0 == "SEND_RETRIES")
+                .count(),
+            1
+        );
+        let mut sorted = attempts[2].2.clone();
+        sorted.sort_by(|(a, _), (b, _)| field_order(a, b));
+        assert_eq!(attempts[2].2, sorted);
+        // Waited 1 ms before the first retry and 2 ms before the second
+        assert_eq!(clock.monotonic(), Duration::from_millis(3));
+
+        let refused = Flaky {
+            failures: Mutex::new(vec![111]),
+            attempts: Sent::default(),
+        };
+        let refused_attempts = refused.attempts.clone();
+        let drain = JournaldDrain::builder()
+            .retry_sends(3)
+            .sink(refused)
+            .build()
+            .unwrap();
+        let result = drain.log(
+            &record!(Level::Info, "", &format_args!("x"), b!()),
+            &OwnedKVList::from(o!()),
+        );
+        assert!(result.is_err());
+        assert_eq!(refused_attempts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn expected_fields_preallocates() {
+        let drain = JournaldDrain::builder()
+            .expected_fields(20)
+            .build()
+            .unwrap();
+        let serializer = Serializer::new(drain.config());
+        assert!(serializer.fields.capacity() >= 20);
+    }
+
+    #[test]
+    fn reuses_field_buffer() {
+        let drain = JournaldDrain::builder().dry_run(true).build().unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("msg"),
+                    b!("a" => 1, "b" => 2)
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let buffer = FIELD_BUFFER.with(Cell::take);
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 6);
+        let address = buffer.as_ptr();
+        FIELD_BUFFER.with(|b| b.set(buffer));
+        let serializer = Serializer::new(drain.config());
+        assert_eq!(serializer.fields.as_ptr(), address);
+    }
+
+    #[test]
+    fn payload_json_matches_fields() {
+        let drain = JournaldDrain::builder().payload_json(true).build().unwrap();
+        let fields = fields(
+            &drain,
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("x"),
+                b!("b" => 2, "a" => "say \"hi\"", "ratio" => 0.5)
+            ),
+            &OwnedKVList::from(o!("flag" => true, "none" => None::<u8>)),
+        );
+        assert_eq!(field(&fields, "A"), Some("say \"hi\""));
+        assert_eq!(field(&fields, "B"), Some("2"));
+        assert_eq!(field(&fields, "FLAG"), Some("true"));
+        assert_eq!(
+            field(&fields, "PAYLOAD_JSON"),
+            Some(
+                r#"{"version":1,"fields":{"a":"say \"hi\"","b":2,"flag":true,"none":null,"ratio":0.5}}"#
+            )
+        );
+        assert_eq!(
+            field(&fields!(JournaldDrain, "a" => 1), "PAYLOAD_JSON"),
+            None
+        );
+    }
+
+    #[test]
+    fn ring_buffer_keeps_last_entries() {
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = RingBufferJournaldDrain::new(JournaldDrain::builder().sink(sink), 3).unwrap();
+        let buffer = drain.buffer();
+        let log = Logger::root(drain.ignore_res(), o!());
+        for i in 0..5 {
+            info!(log, "entry {}", i; "i" => i);
+        }
+        assert_eq!(sent.lock().unwrap().len(), 5);
+        let entries = buffer.dump();
+        let messages = entries.iter().map(|e| &*e.message).collect::<Vec<_>>();
+        assert_eq!(messages, vec!["entry 2", "entry 3", "entry 4"]);
+        assert_eq!(u8::from(entries[0].priority), 5);
+        let fields = entries[2]
+            .fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(field(&fields, "I"), Some("4"));
+    }
+
+    #[test]
+    fn ring_buffer_retains_retried_entries_once() {
+        struct FailsOnce(Mutex<bool>);
+
+        impl JournalSink for FailsOnce {
+            fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+                if std::mem::replace(&mut *self.0.lock().unwrap(), false) {
+                    match Error::test_journald(11) {
+                        Error::Journald(e) => return Err(e),
+                        _ => unreachable!(),
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let builder = JournaldDrain::builder()
+            .sink(FailsOnce(Mutex::new(true)))
+            .retry_sends(1)
+            .send_retries_field(true)
+            .clock(ManualClock::new(std::time::UNIX_EPOCH));
+        let drain = RingBufferJournaldDrain::new(builder, 3).unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("x"), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = drain.dump();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].field("SEND_RETRIES"), Some("1"));
+    }
+
+    #[test]
+    fn epoch_iso_fields() {
+        let drain = JournaldDrain::builder()
+            .epoch_iso_fields(true)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "created_epoch" => 1_700_000_000u64, "other" => 1_700_000_000);
+        assert_eq!(field(&fields, "CREATED_EPOCH"), Some("1700000000"));
+        assert_eq!(
+            field(&fields, "CREATED_EPOCH_ISO"),
+            Some("2023-11-14T22:13:20Z")
+        );
+        assert_eq!(field(&fields, "OTHER_ISO"), None);
+
+        let fields = fields!(JournaldDrain, "created_epoch" => 1_700_000_000u64);
+        assert_eq!(field(&fields, "CREATED_EPOCH_ISO"), None);
+    }
+
+    #[test]
+    fn valid_keys_pass_through() {
+        let fields = fields!(
+            JournaldDrain,
+            "REQUEST_ID" => 1,
+            "HTTP_2XX" => 2,
+            "request_id" => 3,
+            "Mixed.Case" => 4,
+            "_LEADING" => 5
+        );
+        let names = fields.iter().skip(4).map(|f| &*f.0).collect::<Vec<_>>();
+        // Record values are serialized last to first
+        assert_eq!(
+            names,
+            vec![
+                "LEADING",
+                "MIXED_CASE",
+                "REQUEST_ID",
+                "HTTP_2XX",
+                "REQUEST_ID"
+            ]
+        );
+
+        let drain = JournaldDrain::builder()
+            .record_prefix("APP_")
+            .build()
+            .unwrap();
+        assert_eq!(
+            field(&fields!(drain, "REQUEST_ID" => 1), "APP_REQUEST_ID"),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn level_fields() {
+        for &name in &[false, true] {
+            for &priority in &[false, true] {
+                for &severity in &[false, true] {
+                    let level_fields = LevelFields {
+                        name,
+                        priority,
+                        severity,
+                    };
+                    let drain = JournaldDrain::builder()
+                        .level_fields(level_fields)
+                        .build()
+                        .unwrap();
+                    let fields = fields(
+                        &drain,
+                        &record!(Level::Warning, "", &format_args!("x"), b!()),
+                        &OwnedKVList::from(o!()),
+                    );
+                    assert_eq!(
+                        field(&fields, "LEVEL"),
+                        if name { Some("WARNING") } else { None }
+                    );
+                    let expected = if priority { Some("4") } else { None };
+                    assert_eq!(field(&fields, "LEVEL_PRIORITY"), expected);
+                    let expected = if severity { Some("13") } else { None };
+                    assert_eq!(field(&fields, "SEVERITY"), expected);
+                    assert_eq!(field(&fields, "PRIORITY"), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn drop_reasons() {
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        let switch = Arc::new(AtomicBool::new(false));
+        let drain = {
+            let dropped = dropped.clone();
+            JournaldDrain::builder()
+                .kill_switch(switch.clone())
+                .min_level(Level::Debug)
+                .sample(Priority::Warning, 2)
+                .estimate_rate_limit(Duration::from_secs(3600), 2)
+                .drop_rate_limited(true)
+                .on_drop(move |reason, record| {
+                    let msg = record.msg().to_string();
+                    dropped.lock().unwrap().push((reason, msg));
+                })
+                .sink(MockSink::default())
+                .build()
+                .unwrap()
+        };
+        let values = OwnedKVList::from(o!());
+        let log = |level, msg: &str| {
+            let _ = match level {
+                Level::Trace => drain.log(
+                    &record!(Level::Trace, "", &format_args!("{}", msg), b!()),
+                    &values,
+                ),
+                Level::Warning => drain.log(
+                    &record!(Level::Warning, "", &format_args!("{}", msg), b!()),
+                    &values,
+                ),
+                _ => drain.log(
+                    &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                    &values,
+                ),
+            };
+        };
+        log(Level::Trace, "trace");
+        log(Level::Warning, "warn 1");
+        log(Level::Warning, "warn 2");
+        log(Level::Info, "info 1");
+        log(Level::Info, "info 2");
+        switch.store(true, Ordering::Relaxed);
+        log(Level::Info, "killed");
+
+        assert_eq!(
+            *dropped.lock().unwrap(),
+            vec![
+                (DropReason::BelowMinLevel, "trace".to_string()),
+                (DropReason::Sampled, "warn 2".to_string()),
+                (DropReason::RateLimited, "info 2".to_string()),
+                (DropReason::KillSwitch, "killed".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn priority_map() {
+        let drain = JournaldDrain::with_priority_map(|level| match level {
+            Level::Info => Priority::Info,
+            level => JournaldDrain::default_priority(level),
+        });
+        assert_eq!(u8::from(drain.config().priority(Level::Info)), 6);
+        assert_eq!(u8::from(drain.config().priority(Level::Debug)), 6);
+        assert_eq!(u8::from(drain.config().priority(Level::Warning)), 4);
+
+        let builder = JournaldDrain::builder()
+            .info_as_notice(true)
+            .priority_map(|_| Priority::Alert);
+        assert_eq!(sent(builder, "info")[0].0, 1);
+    }
+
+    #[test]
+    fn sorted_fields() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .sort_fields(true)
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("sorted"),
+                    b!("zeta" => 1, "alpha" => 2)
+                ),
+                &OwnedKVList::from(o!("mid" => 3)),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        let names = entries[0]
+            .2
+            .iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "CODE_FILE",
+                "CODE_LINE",
+                "CODE_MODULE",
+                "CODE_FUNC",
+                "ALPHA",
+                "MID",
+                "ZETA"
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_key_policies() {
+        let sent = |policy| {
+            let sink = MockSink::default();
+            let entries = sink.0.clone();
+            let drain = JournaldDrain::builder()
+                .add_field("SERVICE", "static")
+                .duplicate_keys(policy)
+                .sink(sink)
+                .build()
+                .unwrap();
+            drain
+                .log(
+                    &record!(
+                        Level::Info,
+                        "",
+                        &format_args!("msg"),
+                        b!("user" => "record", "service" => "record")
+                    ),
+                    &OwnedKVList::from(o!("user" => "logger")),
+                )
+                .unwrap();
+            let entries = entries.lock().unwrap();
+            let values = |name| {
+                entries[0]
+                    .2
+                    .iter()
+                    .filter(|(k, _)| k == name)
+                    .map(|(_, v)| v.clone())
+                    .collect::<Vec<_>>()
+            };
+            (values("USER"), values("SERVICE"))
+        };
+        assert_eq!(
+            sent(DuplicateKeyPolicy::KeepAll),
+            (
+                vec!["logger".into(), "record".into()],
+                vec!["static".into(), "record".into()]
+            )
+        );
+        assert_eq!(
+            sent(DuplicateKeyPolicy::KeepLast),
+            (vec!["record".into()], vec!["record".into()])
+        );
+        assert_eq!(
+            sent(DuplicateKeyPolicy::KeepFirst),
+            (vec!["logger".into()], vec!["static".into()])
+        );
+    }
+
+    #[test]
+    fn effective_id_fields() {
+        let drain = JournaldDrain::builder()
+            .effective_id_fields(true)
+            .build()
+            .unwrap();
+        let fields = fields!(drain,);
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        assert_eq!(field(&fields, "APP_EUID"), Some(&*euid.to_string()));
+        assert_eq!(field(&fields, "APP_EGID"), Some(&*egid.to_string()));
+        assert_eq!(field(&fields!(JournaldDrain,), "APP_EUID"), None);
+    }
+
+    /// Sink failing with `errno` and counting the attempts
+    struct FailingSink(i32, Arc<Mutex<usize>>);
+
+    impl JournalSink for FailingSink {
+        fn send(&self, _: Priority, _: &str, _: &[Field]) -> Result<(), SdError> {
+            *self.1.lock().unwrap() += 1;
+            let error = io::Error::from_raw_os_error(self.0);
+            Err(SdError::from(format!(
+                "failed to print to journal: {}",
+                error
+            )))
+        }
+    }
+
+    /// Drain keeping the messages of the records
+    #[derive(Default, Clone)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl Drain for Messages {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn falls_back_without_journald() {
+        let fallback = |errno| {
+            let attempts = Arc::new(Mutex::new(0));
+            let drain = JournaldDrain::builder()
+                .sink(FailingSink(errno, attempts.clone()))
+                .build()
+                .unwrap();
+            let messages = Messages::default();
+            let drain = drain.with_fallback(messages.clone());
+            for msg in &["first", "second"] {
+                drain
+                    .log(
+                        &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                        &OwnedKVList::from(o!()),
+                    )
+                    .unwrap();
+            }
+            let messages = messages.0.lock().unwrap().clone();
+            let attempts = *attempts.lock().unwrap();
+            (messages, attempts, drain.journald_available())
+        };
+        let both = vec!["first".to_string(), "second".to_string()];
+        // No socket: only tried once
+        assert_eq!(fallback(2), (both.clone(), 1, false));
+        // Journald is busy: every record is tried
+        assert_eq!(fallback(11), (both, 2, true));
+
+        let messages = Messages::default();
+        let drain = JournaldDrain::builder()
+            .sink(MockSink::default())
+            .build()
+            .unwrap()
+            .with_fallback(messages.clone());
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("sent"), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        assert!(messages.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn message_fields() {
+        let sink = MockSink::default();
+        let entries = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .context_prefix("CTX_")
+            .message_fields(vec!["request_id", "status", "missing"])
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("Request done"),
+                    b!("status" => 500)
+                ),
+                &OwnedKVList::from(o!("request_id" => "abc")),
+            )
+            .unwrap();
+        drain
+            .log(
+                &record!(Level::Info, "", &format_args!("Nothing"), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries[0].1, "Request done [request_id=abc status=500]");
+        assert_eq!(field(&entries[0].2, "CTX_REQUEST_ID"), Some("abc"));
+        assert_eq!(field(&entries[0].2, "STATUS"), Some("500"));
+        assert_eq!(entries[1].1, "Nothing");
+    }
+
+    #[test]
+    fn test_drain_captures_entries() {
+        let builder = JournaldDrain::builder()
+            .record_prefix("REC_")
+            .priority_sink(Priority::Error, MockSink::default());
+        let drain = TestJournaldDrain::with_builder(builder).unwrap();
+        let entries = drain.entries();
+        drain
+            .log(
+                &record!(
+                    Level::Error,
+                    "",
+                    &format_args!("failed"),
+                    b!("foo" => "bar")
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(u8::from(entries[0].priority), 3);
+        assert_eq!(entries[0].message, "failed");
+        assert_eq!(entries[0].field("REC_FOO"), Some("bar"));
+        assert!(entries[0].field("CODE_LINE").is_some());
+        assert_eq!(entries[0].field("FOO"), None);
+    }
+
+    #[test]
+    fn thread_fields() {
+        let drain = JournaldDrain::builder()
+            .thread_fields(true)
+            .build()
+            .unwrap();
+        let drain = Arc::new(drain);
+        let logged = |name: Option<&str>| {
+            let drain = drain.clone();
+            let builder = match name {
+                Some(name) => thread::Builder::new().name(name.to_string()),
+                None => thread::Builder::new(),
+            };
+            let thread = builder.spawn(move || {
+                let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+                (tid.to_string(), fields!(drain,))
+            });
+            thread.unwrap().join().unwrap()
+        };
+
+        let (tid, fields) = logged(Some("worker"));
+        assert_eq!(field(&fields, "TID"), Some(&*tid));
+        assert_eq!(field(&fields, "THREAD_NAME"), Some("worker"));
+        let (tid, fields) = logged(None);
+        assert_eq!(field(&fields, "TID"), Some(&*tid));
+        assert_eq!(field(&fields, "THREAD_NAME"), None);
+        assert_eq!(field(&fields!(JournaldDrain,), "TID"), None);
+    }
+
+    #[test]
+    fn key_prefix() {
+        let drain = JournaldDrain::builder().key_prefix("APP_").build().unwrap();
+        let fields = fields(
+            &drain,
+            &record!(Level::Info, "", &format_args!("msg"), b!("message" => "a")),
+            &OwnedKVList::from(o!("priority" => "b")),
+        );
+        assert_eq!(field(&fields, "APP_MESSAGE"), Some("a"));
+        assert_eq!(field(&fields, "APP_PRIORITY"), Some("b"));
+        assert_eq!(field(&fields, "MESSAGE_"), None);
+        assert!(field(&fields, "CODE_FILE").is_some());
+        assert!(JournaldDrain::builder().key_prefix("app").build().is_err());
+    }
+
+    #[test]
+    fn key_sanitizer() {
+        struct Strict;
+
+        impl KeySanitizer for Strict {
+            fn sanitize<'a>(&self, key: &'a str) -> Option<Cow<'a, str>> {
+                if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+                    Some(Cow::Owned(key.to_ascii_uppercase().replace('.', "_")))
+                } else {
+                    None
+                }
+            }
+        }
+
+        let drain = JournaldDrain::builder()
+            .key_sanitizer(Strict)
+            .record_prefix("REC_")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "http.status" => 500, "bad key!" => 1, "message" => "m");
+        assert_eq!(field(&fields, "REC_HTTP_STATUS"), Some("500"));
+        assert_eq!(field(&fields, "REC_MESSAGE"), Some("m"));
+        assert!(fields.iter().all(|(k, _)| !k.starts_with("REC_BAD")));
+        assert!(fields.iter().all(|(k, _)| k != "REC_"));
+    }
+
+    #[cfg(feature = "log_errno")]
+    #[test]
+    fn errno_extractor() {
+        let drain = JournaldDrain::builder()
+            .errno_extractor(|error| match error.downcast_ref::<Chain>() {
+                Some(&Chain(0, _)) => Some(13),
+                _ => None,
+            })
+            .build()
+            .unwrap();
+        let error = Chain::new(2);
+        let fields = fields!(drain, "err" => &error as &(dyn std::error::Error + 'static));
+        let errnos: Vec<&str> = fields
+            .iter()
+            .filter(|(k, _)| k == "ERRNO")
+            .map(|(_, v)| &**v)
+            .collect();
+        assert_eq!(errnos, ["13"]);
+
+        let error = io::Error::from_raw_os_error(2);
+        let fields = fields!(drain, "err" => &error as &(dyn std::error::Error + 'static));
+        assert_eq!(field(&fields, "ERRNO"), Some("2"));
+    }
+
+    #[cfg(feature = "log_errno_nix")]
+    #[test]
+    fn nix_errno() {
+        let error = nix::errno::Errno::EACCES;
+        let fields = fields!(JournaldDrain, "err" => &error as &(dyn std::error::Error + 'static));
+        assert_eq!(field(&fields, "ERRNO"), Some("13"));
+    }
+
+    #[test]
+    fn min_level_disables_levels() {
+        let drain = JournaldDrain::builder()
+            .min_level(Level::Info)
+            .build()
+            .unwrap();
+        assert!(drain.is_enabled(Level::Warning));
+        assert!(drain.is_enabled(Level::Info));
+        assert!(!drain.is_enabled(Level::Debug));
+        assert!(JournaldDrain.is_enabled(Level::Trace));
+
+        let capture =
+            TestJournaldDrain::with_builder(JournaldDrain::builder().min_level(Level::Info))
+                .unwrap();
+        let logger = Logger::root(capture.fuse(), o!());
+        assert!(!logger.is_enabled(Level::Debug));
+        assert!(logger.is_enabled(Level::Error));
+    }
+
+    #[test]
+    fn float_format() {
+        let formatted = |format, non_finite| {
+            let drain = JournaldDrain::builder()
+                .float_format(format)
+                .non_finite_floats(non_finite)
+                .build()
+                .unwrap();
+            let fields = fields!(drain, "a" => 1500000.0f64, "b" => 0.125f32, "c" => f64::NAN, "d" => f64::NEG_INFINITY);
+            ["A", "B", "C", "D"]
+                .iter()
+                .map(|&name| field(&fields, name).map(str::to_string))
+                .collect::<Vec<_>>()
+        };
+        let some = |values: &[&str]| {
+            values
+                .iter()
+                .map(|v| Some(v.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            formatted(FloatFormat::Display, NonFiniteFloats::Display),
+            some(&["1500000", "0.125", "NaN", "-inf"])
+        );
+        assert_eq!(
+            formatted(FloatFormat::Fixed(2), NonFiniteFloats::Lowercase),
+            some(&["1500000.00", "0.12", "nan", "-inf"])
+        );
+        assert_eq!(
+            formatted(FloatFormat::Scientific(2), NonFiniteFloats::Drop),
+            vec![
+                Some("1.50e6".to_string()),
+                Some("1.25e-1".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn namespace() {
+        for invalid in &["", ".hidden", "a/b", "a b"] {
+            assert!(
+                JournaldDrain::with_namespace(*invalid).is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+        let drain = JournaldDrain::with_namespace("slog-journald-test.missing").unwrap();
+        let error = drain
+            .log(
+                &record!(Level::Info, "", &format_args!("msg"), b!()),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap_err();
+        let error = match error {
+            Error::Journald(e) => e,
+            e => panic!("unexpected error {:?}", e),
+        };
+        assert_eq!(sink::raw_os_error(&error), Some(libc::ENOENT));
+        assert!(error
+            .to_string()
+            .contains("/run/systemd/journal.slog-journald-test.missing/socket"));
+    }
+
+    #[test]
+    fn source_timestamp_key() {
+        let drain = JournaldDrain::builder()
+            .source_timestamp_key("occurred_at")
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "occurred_at" => 1_700_000_000_123_456u64);
+        assert_eq!(
+            field(&fields, "SOURCE_REALTIME_TIMESTAMP"),
+            Some("1700000000123456")
+        );
+        assert_eq!(field(&fields, "OCCURRED_AT"), None);
+        let fields = fields!(drain, "occurred_at" => "1700000000123456");
+        assert_eq!(
+            field(&fields, "SOURCE_REALTIME_TIMESTAMP"),
+            Some("1700000000123456")
+        );
+        assert_eq!(field(&fields!(drain,), "SOURCE_REALTIME_TIMESTAMP"), None);
+
+        let invalid = drain.serialize(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("msg"),
+                b!("occurred_at" => "yesterday")
+            ),
+            &OwnedKVList::from(o!()),
+        );
+        assert!(invalid.is_err());
+
+        let fields = fields!(JournaldDrain, "occurred_at" => 1u64);
+        assert_eq!(field(&fields, "OCCURRED_AT"), Some("1"));
+    }
+
+    #[test]
+    fn journald_fields_match_sent_entry() {
+        let sink = MockSink::default();
+        let sent = sink.0.clone();
+        let drain = JournaldDrain::builder()
+            .max_field_len(20)
+            .content_hash(true)
+            .sink(sink)
+            .build()
+            .unwrap();
+        let values = OwnedKVList::from(o!("ctx" => 1));
+        let log = |record: &Record| {
+            drain.log(record, &values).unwrap();
+            drain.journald_fields(record, &values).unwrap()
+        };
+        let fields = log(&record!(
+            Level::Error,
+            "",
+            &format_args!("failed"),
+            b!("path" => "/a/very/long/path/name")
+        ));
+        let fields: Vec<(String, String)> = fields
+            .into_iter()
+            .map(|(k, v)| (k.into_owned(), v))
+            .collect();
+        let entry = &sent.lock().unwrap()[0];
+        assert_eq!(fields[0], ("PRIORITY".to_string(), entry.0.to_string()));
+        assert_eq!(fields[1], ("MESSAGE".to_string(), entry.1.clone()));
+        assert_eq!(fields[2..], entry.2[..]);
+        assert_eq!(field(&fields, "TRUNCATED"), Some("1"));
+        assert!(field(&fields, "CONTENT_HASH").is_some());
+    }
+
+    #[test]
+    fn skip_failed_values() {
+        struct Failing;
+
+        impl slog::Value for Failing {
+            fn serialize(&self, _: &Record, _: Key, _: &mut dyn slog::Serializer) -> slog::Result {
+                Err(slog::Error::Other)
+            }
+        }
+
+        let entry = |drain: &JournaldDrain| {
+            // Serialized from last to first, so `a` comes after the failing value
+            drain
+                .serialize(
+                    &record!(
+                        Level::Info,
+                        "",
+                        &format_args!("msg"),
+                        b!("a" => 1, "bad" => Failing, "message_id" => "not-an-id", "c" => 3)
+                    ),
+                    &OwnedKVList::from(o!("ctx" => "x")),
+                )
+                .map(|mut serializer| {
+                    let fields = std::mem::take(&mut serializer.fields);
+                    let fields = fields.into_iter().map(|(k, v)| (k.into_owned(), v));
+                    fields.collect::<Vec<_>>()
+                })
+        };
+        let drain = JournaldDrain::builder()
+            .skip_failed_values(true)
+            .build()
+            .unwrap();
+        let fields = entry(&drain).unwrap();
+        assert_eq!(field(&fields, "CTX"), Some("x"));
+        assert_eq!(field(&fields, "C"), Some("3"));
+        // slog's `KV` for tuples stops at the failing value, `a` is never serialized
+        assert_eq!(field(&fields, "A"), None);
+        assert_eq!(field(&fields, "MESSAGE_ID"), None);
+        assert_eq!(field(&fields, "SLOG_SERIALIZE_ERRORS"), Some("2"));
+
+        assert!(entry(&JournaldDrain).is_err());
+
+        // Values failing in the drain's serializer don't affect the values after them
+        let fields = fields!(
+            drain,
+            "a" => 1,
+            "message_id" => "not-an-id",
+            "c" => 3,
+        );
+        assert_eq!(field(&fields, "A"), Some("1"));
+        assert_eq!(field(&fields, "C"), Some("3"));
+        assert_eq!(field(&fields, "SLOG_SERIALIZE_ERRORS"), Some("1"));
+
+        // The payload doesn't count the failing value again
+        let drain = JournaldDrain::builder()
+            .skip_failed_values(true)
+            .payload_json(true)
+            .build()
+            .unwrap();
+        let fields = entry(&drain).unwrap();
+        assert_eq!(field(&fields, "SLOG_SERIALIZE_ERRORS"), Some("2"));
+        assert_eq!(field(&fields, "PAYLOAD_JSON"), None);
+    }
+
+    #[test]
+    fn invalid_keys() {
+        // One byte longer than journald accepts
+        const LONG: &str = "kkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkkk";
+        let entry = |policy| {
+            let drain = JournaldDrain::builder()
+                .invalid_keys(policy)
+                .build()
+                .unwrap();
+            fields!(drain, "!*" => 1, "ok" => 2, "42" => 3, LONG => 4)
+        };
+
+        let fields = entry(InvalidKeyPolicy::Count);
+        assert_eq!(field(&fields, "OK"), Some("2"));
+        assert_eq!(field(&fields, "SLOG_DROPPED_FIELDS"), Some("3"));
+        assert!(fields.iter().all(|(k, _)| builder::is_valid_field_name(k)));
+
+        let fields = entry(InvalidKeyPolicy::Rename);
+        // Serialized from last to first
+        assert_eq!(field(&fields, "FIELD_1"), Some("4"));
+        assert_eq!(field(&fields, "FIELD_2"), Some("3"));
+        assert_eq!(field(&fields, "FIELD_3"), Some("1"));
+        assert_eq!(field(&fields, "SLOG_DROPPED_FIELDS"), None);
+
+        // Renamed values don't take the names of other fields, serialized before or after them
+        let drain = JournaldDrain::builder()
+            .invalid_keys(InvalidKeyPolicy::Rename)
+            .build()
+            .unwrap();
+        let fields = fields!(drain, "field_3" => "c", "!*" => 1, "field_1" => "a", "?" => 2,);
+        assert_eq!(field(&fields, "FIELD_1"), Some("a"));
+        assert_eq!(field(&fields, "FIELD_2"), Some("2"));
+        assert_eq!(field(&fields, "FIELD_3"), Some("c"));
+        assert_eq!(field(&fields, "FIELD_4"), Some("1"));
+        assert_eq!(
+            fields
+                .iter()
+                .filter(|(k, _)| k.starts_with("FIELD_"))
+                .count(),
+            4
+        );
+
+        let fields = entry(InvalidKeyPolicy::Drop);
+        assert_eq!(field(&fields, "SLOG_DROPPED_FIELDS"), None);
+        assert!(fields.iter().all(|(k, _)| builder::is_valid_field_name(k)));
+
+        let fields = fields!(JournaldDrain, "ok" => 1);
+        assert_eq!(field(&fields, "SLOG_DROPPED_FIELDS"), None);
+    }
+
+    #[test]
+    fn syslog_identifier() {
+        let drain = JournaldDrain::builder()
+            .syslog_identifier("myservice")
+            .well_known_fields(WellKnownFields::new().object_pid(42))
+            .build()
+            .unwrap();
+        let fields = fields!(drain,);
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("myservice"));
+        assert_eq!(field(&fields, "OBJECT_PID"), Some("42"));
+
+        // A logged identifier doesn't add a second one
+        let fields = fields!(drain, "syslog_identifier" => "other");
+        assert_eq!(
+            fields
+                .iter()
+                .filter(|(k, _)| k == "SYSLOG_IDENTIFIER")
+                .count(),
+            1
+        );
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("myservice"));
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER_"), Some("other"));
+
+        // Nor does a static field, which would skip the validation
+        let built = JournaldDrain::builder()
+            .syslog_identifier("a")
+            .add_field("SYSLOG_IDENTIFIER", "b c")
+            .build();
+        assert!(built.is_err());
+
+        let drain = JournaldDrain::builder()
+            .syslog_identifier("fallback")
+            .well_known_fields(WellKnownFields::new().syslog_identifier("myservice"))
+            .build()
+            .unwrap();
+        let fields = fields!(drain,);
+        assert_eq!(field(&fields, "SYSLOG_IDENTIFIER"), Some("myservice"));
+
+        let built = JournaldDrain::builder()
+            .syslog_identifier("my service")
+            .build();
+        assert!(built.is_err());
+    }
 }
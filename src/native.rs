@@ -0,0 +1,150 @@
+//! Journald's native protocol, as sent over `/run/systemd/journal/socket`.
+
+use std::io::{self, Write};
+
+use libsystemd::logging::Priority;
+use sink::{BinaryField, Field};
+
+/// Encode an entry as one datagram of the native protocol.
+///
+/// Each field is sent as `NAME=value\n`. Values containing a newline are framed as
+/// `NAME\n`, the value length as 64-bit little-endian integer, the value and `\n`.
+pub(crate) fn encode(priority: Priority, message: &str, fields: &[Field]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let priority = u8::from(priority).to_string();
+    encode_field(&mut entry, "PRIORITY", priority.as_bytes());
+    encode_field(&mut entry, "MESSAGE", message.as_bytes());
+    for (name, value) in fields {
+        encode_field(&mut entry, name, value.as_bytes());
+    }
+    entry
+}
+
+/// Append the `binary` fields to an encoded entry, always length-framed.
+pub(crate) fn encode_binary(entry: &mut Vec<u8>, binary: &[BinaryField]) {
+    for (name, value) in binary {
+        encode_framed(entry, name, value, true);
+    }
+}
+
+/// Encode an entry in the Journal Export Format, as read by `systemd-journal-remote`.
+///
+/// The fields are framed like in the native protocol, except that values with any
+/// control character are length-framed, with `__REALTIME_TIMESTAMP` in microseconds
+/// since the epoch first. The entry ends with an empty line.
+#[cfg(feature = "remote")]
+pub(crate) fn encode_export(
+    priority: Priority,
+    message: &str,
+    fields: &[Field],
+    binary: &[BinaryField],
+    realtime: std::time::SystemTime,
+) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let micros = realtime
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros());
+    let micros = micros.to_string();
+    encode_export_field(&mut entry, "__REALTIME_TIMESTAMP", micros.as_bytes());
+    let priority = u8::from(priority).to_string();
+    encode_export_field(&mut entry, "PRIORITY", priority.as_bytes());
+    encode_export_field(&mut entry, "MESSAGE", message.as_bytes());
+    for (name, value) in fields {
+        encode_export_field(&mut entry, name, value.as_bytes());
+    }
+    encode_binary(&mut entry, binary);
+    entry.push(b'\n');
+    entry
+}
+
+fn encode_field(entry: &mut Vec<u8>, name: &str, value: &[u8]) {
+    let binary = value.contains(&b'\n');
+    encode_framed(entry, name, value, binary);
+}
+
+#[cfg(feature = "remote")]
+fn encode_export_field(entry: &mut Vec<u8>, name: &str, value: &[u8]) {
+    let binary = value.iter().any(|&b| b < 0x20 || b == 0x7f);
+    encode_framed(entry, name, value, binary);
+}
+
+fn encode_framed(entry: &mut Vec<u8>, name: &str, value: &[u8], binary: bool) {
+    entry.extend_from_slice(name.as_bytes());
+    if binary {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value);
+    entry.push(b'\n');
+}
+
+/// Write the encoded entry and an empty line to `out`, stderr for
+/// [`debug_dump`](../struct.JournaldDrainBuilder.html#method.debug_dump).
+pub(crate) fn dump<W: Write>(
+    out: &mut W,
+    priority: Priority,
+    message: &str,
+    fields: &[Field],
+    binary: &[BinaryField],
+) -> io::Result<()> {
+    let mut entry = encode(priority, message, fields);
+    encode_binary(&mut entry, binary);
+    entry.push(b'\n');
+    out.write_all(&entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn encodes_known_entry() {
+        let fields = vec![
+            (Cow::Borrowed("CODE_LINE"), "42".to_string()),
+            (Cow::Borrowed("TEXT"), "two\nlines".to_string()),
+        ];
+        let mut expected = b"PRIORITY=5\nMESSAGE=hello\nCODE_LINE=42\nTEXT\n".to_vec();
+        expected.extend_from_slice(&[9, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"two\nlines\n");
+        assert_eq!(encode(Priority::Notice, "hello", &fields), expected);
+    }
+
+    #[test]
+    fn encodes_binary_fields() {
+        let mut entry = Vec::new();
+        encode_binary(&mut entry, &[(Cow::Borrowed("DATA"), vec![0, 0xff])]);
+        assert_eq!(entry, b"DATA\n\x02\0\0\0\0\0\0\0\0\xff\n".to_vec());
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn encodes_export_entry() {
+        let fields = vec![
+            (Cow::Borrowed("CODE_LINE"), "42".to_string()),
+            (Cow::Borrowed("TEXT"), "tab\there".to_string()),
+        ];
+        let realtime = std::time::UNIX_EPOCH + std::time::Duration::from_micros(1_500_000);
+        let mut expected =
+            b"__REALTIME_TIMESTAMP=1500000\nPRIORITY=5\nMESSAGE=hello\nCODE_LINE=42\nTEXT\n"
+                .to_vec();
+        expected.extend_from_slice(&[8, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"tab\there\n\n");
+        let entry = encode_export(Priority::Notice, "hello", &fields, &[], realtime);
+        assert_eq!(entry, expected);
+    }
+
+    #[test]
+    fn dumps_entry_with_separator() {
+        let fields = vec![(Cow::Borrowed("CODE_LINE"), "42".to_string())];
+        let binary = vec![(Cow::Borrowed("DATA"), vec![0xff])];
+        let mut out = Vec::new();
+        dump(&mut out, Priority::Error, "hello", &fields, &binary).unwrap();
+        let mut expected = b"PRIORITY=3\nMESSAGE=hello\nCODE_LINE=42\nDATA\n".to_vec();
+        expected.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"\xff\n\n");
+        assert_eq!(out, expected);
+    }
+}
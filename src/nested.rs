@@ -0,0 +1,628 @@
+//! Support for nested values, logged with slog's `nested-values` feature.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::panic::RefUnwindSafe;
+
+use serde::ser::{self, Serialize};
+use serde_json::Value;
+use slog::SerdeValue;
+
+/// Formatter registered with
+/// [`JournaldDrainBuilder::serde_formatter`](../struct.JournaldDrainBuilder.html#method.serde_formatter).
+pub(crate) type SerdeFormatter = Box<dyn Fn(&Value) -> String + Send + Sync + RefUnwindSafe>;
+
+/// Format `value` with the formatter registered for its serde type name, if there is one.
+pub(crate) fn format_registered(
+    formatters: &HashMap<String, SerdeFormatter>,
+    value: &dyn SerdeValue,
+) -> Result<Option<String>, slog::Error> {
+    if formatters.is_empty() {
+        return Ok(None);
+    }
+    let name = match value.as_serde().serialize(TypeName) {
+        Ok(Some(name)) => name,
+        _ => return Ok(None),
+    };
+    match formatters.get(name) {
+        Some(formatter) => {
+            let json = serde_json::to_value(value.as_serde())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Some(formatter(&json)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Error of [`TypeName`], which can only come from the value's `Serialize` implementation.
+#[derive(Debug)]
+struct ProbeError;
+
+impl Display for ProbeError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str("failed to serialize value")
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl ser::Error for ProbeError {
+    fn custom<T: Display>(_msg: T) -> ProbeError {
+        ProbeError
+    }
+}
+
+/// Serializer finding out the serde name of a value's type.
+///
+/// Returns `None` for values without a name, like primitives, sequences and maps.
+/// The contents of compound values are skipped.
+struct TypeName;
+
+/// The compound serializers of [`TypeName`], ignoring all elements.
+struct Named(Option<&'static str>);
+
+macro_rules! unnamed {
+    ($($name:ident : $T:ty),*) => {
+        $(
+            fn $name(self, _v: $T) -> Result<Option<&'static str>, ProbeError> {
+                Ok(None)
+            }
+        )*
+    };
+}
+
+macro_rules! skip_elements {
+    ($($Trait:ident { $($method:ident),* })*) => {
+        $(
+            impl ser::$Trait for Named {
+                type Ok = Option<&'static str>;
+                type Error = ProbeError;
+
+                $(
+                    fn $method<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), ProbeError> {
+                        Ok(())
+                    }
+                )*
+
+                fn end(self) -> Result<Option<&'static str>, ProbeError> {
+                    Ok(self.0)
+                }
+            }
+        )*
+    };
+}
+
+skip_elements! {
+    SerializeSeq { serialize_element }
+    SerializeTuple { serialize_element }
+    SerializeTupleStruct { serialize_field }
+    SerializeTupleVariant { serialize_field }
+    SerializeMap { serialize_key, serialize_value }
+}
+
+impl ser::SerializeStruct for Named {
+    type Ok = Option<&'static str>;
+    type Error = ProbeError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), ProbeError> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<&'static str>, ProbeError> {
+        Ok(self.0)
+    }
+}
+
+impl ser::SerializeStructVariant for Named {
+    type Ok = Option<&'static str>;
+    type Error = ProbeError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        _value: &T,
+    ) -> Result<(), ProbeError> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<Option<&'static str>, ProbeError> {
+        Ok(self.0)
+    }
+}
+
+impl ser::Serializer for TypeName {
+    type Ok = Option<&'static str>;
+    type Error = ProbeError;
+    type SerializeSeq = Named;
+    type SerializeTuple = Named;
+    type SerializeTupleStruct = Named;
+    type SerializeTupleVariant = Named;
+    type SerializeMap = Named;
+    type SerializeStruct = Named;
+    type SerializeStructVariant = Named;
+
+    unnamed!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8]
+    );
+
+    fn serialize_none(self) -> Result<Option<&'static str>, ProbeError> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(
+        self,
+        value: &T,
+    ) -> Result<Option<&'static str>, ProbeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Option<&'static str>, ProbeError> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Option<&'static str>, ProbeError> {
+        Ok(Some(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Option<&'static str>, ProbeError> {
+        Ok(Some(name))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _value: &T,
+    ) -> Result<Option<&'static str>, ProbeError> {
+        Ok(Some(name))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Option<&'static str>, ProbeError> {
+        Ok(Some(name))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Named, ProbeError> {
+        Ok(Named(None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Named, ProbeError> {
+        Ok(Named(None))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Named, ProbeError> {
+        Ok(Named(Some(name)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Named, ProbeError> {
+        Ok(Named(Some(name)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Named, ProbeError> {
+        Ok(Named(None))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Named, ProbeError> {
+        Ok(Named(Some(name)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Named, ProbeError> {
+        Ok(Named(Some(name)))
+    }
+}
+
+/// Flatten `value`, logged under `key`, into `(path, value)` pairs.
+///
+/// The path of a nested value is the keys leading to it joined with `.`, e.g. the field
+/// `id` of a struct in field `user` of a value logged as `ctx` ends up as `ctx.user.id`.
+///
+/// For enums the variant name is the value at the enum's path, any data of the variant
+/// is flattened below the variant name. E.g. `State::Failed { code: 3 }` logged as
+/// `state` becomes `state=Failed` and `state.Failed.code=3`.
+///
+/// Sequences and tuples end up as a single JSON array, or with `indexed` flattened
+/// below the index of each element, e.g. `tags.0` and `tags.1`.
+pub(crate) fn flatten(
+    key: &str,
+    value: &dyn SerdeValue,
+    indexed: bool,
+) -> Result<Vec<(String, String)>, slog::Error> {
+    let mut leaves = Vec::new();
+    value
+        .as_serde()
+        .serialize(Flattener {
+            leaves: &mut leaves,
+            path: key.to_string(),
+            indexed,
+        })
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.0))?;
+    Ok(leaves)
+}
+
+fn join(path: &str, segment: &str) -> String {
+    format!("{}.{}", path, segment)
+}
+
+/// Error from the `Serialize` implementation of a flattened value.
+#[derive(Debug)]
+struct FlattenError(String);
+
+impl Display for FlattenError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FlattenError {}
+
+impl ser::Error for FlattenError {
+    fn custom<T: Display>(msg: T) -> FlattenError {
+        FlattenError(msg.to_string())
+    }
+}
+
+/// Serializer for the value at `path`.
+struct Flattener<'l> {
+    leaves: &'l mut Vec<(String, String)>,
+    path: String,
+    /// Flatten sequences by index instead of sending them as JSON
+    indexed: bool,
+}
+
+impl<'l> Flattener<'l> {
+    fn leaf<T: Display>(self, value: T) -> Result<(), FlattenError> {
+        self.leaves.push((self.path, value.to_string()));
+        Ok(())
+    }
+}
+
+macro_rules! leaf {
+    ($($name:ident : $T:ty),*) => {
+        $(
+            fn $name(self, v: $T) -> Result<(), FlattenError> {
+                self.leaf(v)
+            }
+        )*
+    };
+}
+
+impl<'l> ser::Serializer for Flattener<'l> {
+    type Ok = ();
+    type Error = FlattenError;
+    type SerializeSeq = FlattenSeq<'l>;
+    type SerializeTuple = FlattenSeq<'l>;
+    type SerializeTupleStruct = FlattenSeq<'l>;
+    type SerializeTupleVariant = FlattenSeq<'l>;
+    type SerializeMap = FlattenMap<'l>;
+    type SerializeStruct = FlattenMap<'l>;
+    type SerializeStructVariant = FlattenMap<'l>;
+
+    leaf!(
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str
+    );
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FlattenError> {
+        let value = String::from_utf8_lossy(v).into_owned();
+        self.leaf(value)
+    }
+
+    fn serialize_none(self) -> Result<(), FlattenError> {
+        self.leaf("None")
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), FlattenError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), FlattenError> {
+        self.leaf("")
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), FlattenError> {
+        self.leaf(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), FlattenError> {
+        self.leaf(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        let path = join(&self.path, variant);
+        self.leaves.push((self.path, variant.to_string()));
+        value.serialize(Flattener {
+            leaves: self.leaves,
+            path,
+            indexed: self.indexed,
+        })
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<FlattenSeq<'l>, FlattenError> {
+        Ok(FlattenSeq::new(self, len.unwrap_or(0)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<FlattenSeq<'l>, FlattenError> {
+        Ok(FlattenSeq::new(self, len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<FlattenSeq<'l>, FlattenError> {
+        Ok(FlattenSeq::new(self, len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<FlattenSeq<'l>, FlattenError> {
+        let path = join(&self.path, variant);
+        self.leaves.push((self.path, variant.to_string()));
+        Ok(FlattenSeq::new(
+            Flattener {
+                leaves: self.leaves,
+                path,
+                indexed: self.indexed,
+            },
+            len,
+        ))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<FlattenMap<'l>, FlattenError> {
+        Ok(FlattenMap::new(self))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<FlattenMap<'l>, FlattenError> {
+        Ok(FlattenMap::new(self))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<FlattenMap<'l>, FlattenError> {
+        let path = join(&self.path, variant);
+        self.leaves.push((self.path, variant.to_string()));
+        Ok(FlattenMap::new(Flattener {
+            leaves: self.leaves,
+            path,
+            indexed: self.indexed,
+        }))
+    }
+}
+
+/// Flattens the entries of maps and structs below the path of the map.
+struct FlattenMap<'l> {
+    flattener: Flattener<'l>,
+    /// Path of the value following the last serialized map key
+    value_path: Option<String>,
+}
+
+impl<'l> FlattenMap<'l> {
+    fn new(flattener: Flattener<'l>) -> FlattenMap<'l> {
+        FlattenMap {
+            flattener,
+            value_path: None,
+        }
+    }
+
+    fn entry<T: ?Sized + Serialize>(
+        &mut self,
+        path: String,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        value.serialize(Flattener {
+            leaves: self.flattener.leaves,
+            path,
+            indexed: self.flattener.indexed,
+        })
+    }
+}
+
+impl<'l> ser::SerializeMap for FlattenMap<'l> {
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), FlattenError> {
+        let key = match serde_json::to_value(key).map_err(ser::Error::custom)? {
+            Value::String(key) => key,
+            key => key.to_string(),
+        };
+        self.value_path = Some(join(&self.flattener.path, &key));
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FlattenError> {
+        let path = self
+            .value_path
+            .take()
+            .ok_or_else(|| FlattenError("map value without key".to_string()))?;
+        self.entry(path, value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'l> ser::SerializeStruct for FlattenMap<'l> {
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        let path = join(&self.flattener.path, key);
+        self.entry(path, value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'l> ser::SerializeStructVariant for FlattenMap<'l> {
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+/// Collects the elements of sequences and tuples into a JSON array, or flattens them
+/// below their index.
+struct FlattenSeq<'l> {
+    flattener: Flattener<'l>,
+    elements: Vec<Value>,
+    index: usize,
+}
+
+impl<'l> FlattenSeq<'l> {
+    fn new(flattener: Flattener<'l>, len: usize) -> FlattenSeq<'l> {
+        let len = if flattener.indexed { 0 } else { len };
+        FlattenSeq {
+            flattener,
+            elements: Vec::with_capacity(len),
+            index: 0,
+        }
+    }
+
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FlattenError> {
+        if self.flattener.indexed {
+            let path = join(&self.flattener.path, &self.index.to_string());
+            self.index += 1;
+            return value.serialize(Flattener {
+                leaves: self.flattener.leaves,
+                path,
+                indexed: true,
+            });
+        }
+        let value = serde_json::to_value(value).map_err(ser::Error::custom)?;
+        self.elements.push(value);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), FlattenError> {
+        if self.flattener.indexed {
+            return Ok(());
+        }
+        let array = Value::Array(self.elements);
+        self.flattener.leaf(array)
+    }
+}
+
+macro_rules! flatten_seq {
+    ($($Trait:ident :: $method:ident),*) => {
+        $(
+            impl<'l> ser::$Trait for FlattenSeq<'l> {
+                type Ok = ();
+                type Error = FlattenError;
+
+                fn $method<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FlattenError> {
+                    self.element(value)
+                }
+
+                fn end(self) -> Result<(), FlattenError> {
+                    self.finish()
+                }
+            }
+        )*
+    };
+}
+
+flatten_seq!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
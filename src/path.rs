@@ -0,0 +1,112 @@
+//! Logging of file system paths and binary data.
+
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path};
+
+use slog::{BytesKind, Key, Record, Serializer, Value};
+
+/// Log a path in a normalized form, without losing non-UTF-8 paths.
+///
+/// Logging a `Path` with `.display()` replaces bytes that aren't UTF-8, so two different
+/// paths can end up as the same value, and spellings like `/var//log/./app/` and
+/// `/var/log/app` of the same path as different values. This joins the components of the
+/// path with single `/` and logs UTF-8 paths as text. Other paths are logged as bytes,
+/// which `JournaldDrain` sends as binary field value:
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog_journald::PathValue;
+///
+/// fn main() {
+///     let log = slog_journald::filtered_logger(slog::Level::Info, o!());
+///     info!(log, "Opened config"; "path" => PathValue("/etc//app/./config.toml"));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PathValue<P>(pub P);
+
+impl<P: AsRef<Path>> Value for PathValue<P> {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        match String::from_utf8(normalize(self.0.as_ref())) {
+            Ok(path) => serializer.emit_str(key, &path),
+            Err(e) => serializer.emit_bytes(key, e.as_bytes(), BytesKind::Stream),
+        }
+    }
+}
+
+/// Log bytes that `JournaldDrain` sends as binary field value, e.g. packet payloads.
+///
+/// The value is logged as slog bytes of `BytesKind::Stream`, so other drains format it
+/// as they format bytes, usually hex-encoded. Sinks that can't send binary values get
+/// it hex-encoded as well, see [`JournalSink::send_binary`](trait.JournalSink.html#method.send_binary).
+///
+/// ```
+/// #[macro_use]
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog_journald::BinaryValue;
+///
+/// fn main() {
+///     let log = slog_journald::filtered_logger(slog::Level::Info, o!());
+///     let payload = [0x00, 0xff, b'\n'];
+///     info!(log, "Received packet"; "payload" => BinaryValue(&payload[..]));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct BinaryValue<B>(pub B);
+
+impl<B: AsRef<[u8]>> Value for BinaryValue<B> {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_bytes(key, self.0.as_ref(), BytesKind::Stream)
+    }
+}
+
+/// The bytes of `path`, with its components joined by single separators
+fn normalize(path: &Path) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(path.as_os_str().len());
+    for component in path.components() {
+        if let Component::Normal(_) | Component::CurDir | Component::ParentDir = component {
+            if !normalized.is_empty() && normalized.last() != Some(&b'/') {
+                normalized.push(b'/');
+            }
+        }
+        normalized.extend_from_slice(component.as_os_str().as_bytes());
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn normalizes_separators() {
+        let normalize = |path: &str| String::from_utf8(normalize(Path::new(path))).unwrap();
+        assert_eq!(normalize("/var//log/./app/"), "/var/log/app");
+        assert_eq!(normalize("relative/../path"), "relative/../path");
+        assert_eq!(normalize("./here"), "./here");
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize(""), "");
+    }
+
+    #[test]
+    fn keeps_non_utf8_bytes() {
+        let path = Path::new(OsStr::from_bytes(b"/tmp//caf\xe9/"));
+        assert_eq!(normalize(path), b"/tmp/caf\xe9".to_vec());
+    }
+}
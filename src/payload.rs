@@ -0,0 +1,154 @@
+//! The `PAYLOAD_JSON` field with all logged values as one JSON object.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use slog::{Key, Record, Serializer, KV};
+
+/// Version of the `PAYLOAD_JSON` object, to be incremented on incompatible changes
+const VERSION: u32 = 1;
+
+/// Serialize the logger values and record values as
+/// `{"version":1,"fields":{"key":value,...}}`, with the keys sorted.
+///
+/// Keys are the original keys, not the sanitized field names. A key logged more than once
+/// keeps the value journald would show last.
+pub(crate) fn payload_json(record: &Record, logger_values: &dyn KV) -> Result<String, slog::Error> {
+    let mut payload = Payload(BTreeMap::new());
+    logger_values.serialize(record, &mut payload)?;
+    record.kv().serialize(record, &mut payload)?;
+
+    let mut json = format!("{{\"version\":{},\"fields\":{{", VERSION);
+    for (i, (key, value)) in payload.0.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_string(&mut json, key);
+        json.push(':');
+        json.push_str(value);
+    }
+    json.push_str("}}");
+    Ok(json)
+}
+
+/// Collects the values as JSON text by key
+struct Payload(BTreeMap<String, String>);
+
+impl Payload {
+    fn insert(&mut self, key: Key, json: String) -> slog::Result {
+        self.0.insert(key_string(&key), json);
+        Ok(())
+    }
+
+    fn insert_str(&mut self, key: Key, value: &str) -> slog::Result {
+        let mut json = String::with_capacity(value.len() + 2);
+        write_string(&mut json, value);
+        self.insert(key, json)
+    }
+}
+
+fn key_string(key: &Key) -> String {
+    // Only useless without `slog/dynamic-keys`
+    #[allow(clippy::useless_asref)]
+    let key: &str = key.as_ref();
+    key.to_string()
+}
+
+/// Append `value` as JSON string
+fn write_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+macro_rules! integer {
+    ($($name:ident : $T:ty),*) => {
+        $(
+            fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+                self.insert(key, val.to_string())
+            }
+        )*
+    };
+}
+
+macro_rules! float {
+    ($($name:ident : $T:ty),*) => {
+        $(
+            fn $name(&mut self, key: Key, val: $T) -> slog::Result {
+                if val.is_finite() {
+                    self.insert(key, val.to_string())
+                } else {
+                    // JSON has no representation for infinity and NaN
+                    self.insert_str(key, &val.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl Serializer for Payload {
+    integer!(
+        emit_u8: u8,
+        emit_i8: i8,
+        emit_u16: u16,
+        emit_i16: i16,
+        emit_u32: u32,
+        emit_i32: i32,
+        emit_u64: u64,
+        emit_i64: i64,
+        emit_usize: usize,
+        emit_isize: isize
+    );
+    float!(emit_f32: f32, emit_f64: f64);
+
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        self.insert(key, val.to_string())
+    }
+
+    fn emit_unit(&mut self, key: Key) -> slog::Result {
+        self.insert(key, "null".to_string())
+    }
+
+    fn emit_none(&mut self, key: Key) -> slog::Result {
+        self.insert(key, "null".to_string())
+    }
+
+    fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
+        self.insert_str(key, val)
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.insert_str(key, &val.to_string())
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: Key, value: &dyn slog::SerdeValue) -> slog::Result {
+        let json = serde_json::to_string(value.as_serde())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.insert(key, json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut json = String::new();
+        write_string(&mut json, "a \"quoted\"\\path\n\u{1}");
+        assert_eq!(json, r#""a \"quoted\"\\path\n\u0001""#);
+    }
+}
@@ -0,0 +1,79 @@
+//! Client-side estimate of journald's rate limiting.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use clock::Clock;
+
+/// Mirrors journald's per-service rate limit: at most `burst` entries are accepted
+/// per `interval`, anything beyond that is counted as likely suppressed.
+///
+/// This is only an estimate. Journald scales the burst with the free disk space,
+/// applies the limit to all processes of the service together and the entries of
+/// `systemd-journald` itself aren't visible to the sender.
+pub(crate) struct RateLimitEstimator {
+    interval: Duration,
+    burst: u64,
+    window: Mutex<Window>,
+    suppressed: AtomicU64,
+}
+
+struct Window {
+    /// Monotonic time the window started, none before the first entry
+    start: Option<Duration>,
+    sent: u64,
+}
+
+impl RateLimitEstimator {
+    pub(crate) fn new(interval: Duration, burst: u64) -> RateLimitEstimator {
+        RateLimitEstimator {
+            interval,
+            burst,
+            window: Mutex::new(Window {
+                start: None,
+                sent: 0,
+            }),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Account for an entry journald accepted on the socket, at the time of `clock`.
+    pub(crate) fn record_sent(&self, clock: &dyn Clock) {
+        let now = clock.monotonic();
+        let mut window = match self.window.lock() {
+            Ok(window) => window,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if !self.in_window(&window, now) {
+            window.start = Some(now);
+            window.sent = 0;
+        }
+        window.sent += 1;
+        if window.sent > self.burst {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether journald likely suppresses the next entry, as `burst` entries were sent
+    /// in the current window already.
+    pub(crate) fn at_limit(&self, clock: &dyn Clock) -> bool {
+        let now = clock.monotonic();
+        let window = match self.window.lock() {
+            Ok(window) => window,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        self.in_window(&window, now) && window.sent >= self.burst
+    }
+
+    /// Whether `now` is still within `window`
+    fn in_window(&self, window: &Window, now: Duration) -> bool {
+        window
+            .start
+            .is_some_and(|start| now.saturating_sub(start) < self.interval)
+    }
+
+    pub(crate) fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
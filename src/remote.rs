@@ -0,0 +1,327 @@
+//! Forwarding entries to a remote journal over a stream socket.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libsystemd::errors::SdError;
+use libsystemd::logging::Priority;
+use native;
+use sink::{BinaryField, Field, JournalSink};
+
+/// Sink streaming entries in the Journal Export Format to a remote endpoint, e.g.
+/// `systemd-journal-remote --listen-raw=`, over TCP or a Unix socket.
+///
+/// Requires the `remote` feature. Install it with
+/// [`JournaldDrainBuilder::sink`](struct.JournaldDrainBuilder.html#method.sink):
+///
+/// ```no_run
+/// use slog_journald::{JournaldDrain, RemoteSink};
+///
+/// let sink = RemoteSink::connect_tcp("logs.example.com:19532").unwrap();
+/// let drain = JournaldDrain::builder().sink(sink).build().unwrap();
+/// ```
+///
+/// The connection isn't encrypted or authenticated, there's no TLS support. Use it on
+/// trusted networks or through a tunnel.
+///
+/// If writing an entry fails, the sink reconnects and writes it once more before it
+/// reports the error. Reconnecting doesn't hold up other logging threads, and a TCP
+/// connection attempt gives up after the connect timeout, one second unless set with
+/// [`connect_tcp_timeout`](#method.connect_tcp_timeout). A receiver that doesn't keep up
+/// applies backpressure: writes block until the [`write_timeout`](#method.write_timeout)
+/// and then fail like any other error.
+/// Entries written right before the connection broke can be lost without an error, as
+/// the failure is only noticed on a later write.
+///
+/// Entries are timestamped with the time they're sent, or the `SOURCE_REALTIME_TIMESTAMP`
+/// field if they have one, see
+/// [`JournaldDrainBuilder::source_timestamp_key`](struct.JournaldDrainBuilder.html#method.source_timestamp_key).
+#[derive(Debug)]
+pub struct RemoteSink {
+    target: Target,
+    connect_timeout: Duration,
+    write_timeout: Option<Duration>,
+    stream: Mutex<Option<Stream>>,
+}
+
+/// How long to wait for a TCP connection by default
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+enum Target {
+    Tcp(Vec<SocketAddr>),
+    Unix(PathBuf),
+}
+
+#[derive(Debug)]
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl RemoteSink {
+    /// Connect to a remote journal listening on TCP `addr`.
+    ///
+    /// The address is resolved once, reconnecting uses the same addresses.
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<RemoteSink> {
+        RemoteSink::connect_tcp_timeout(addr, DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    /// Connect to a remote journal listening on TCP `addr`, giving up on each connection
+    /// attempt after `timeout`, also when reconnecting.
+    ///
+    /// Fails if `timeout` is zero.
+    pub fn connect_tcp_timeout<A: ToSocketAddrs>(
+        addr: A,
+        timeout: Duration,
+    ) -> io::Result<RemoteSink> {
+        let addrs = addr.to_socket_addrs()?.collect();
+        RemoteSink::connect(Target::Tcp(addrs), timeout)
+    }
+
+    /// Connect to a remote journal listening on the Unix stream socket at `path`.
+    ///
+    /// Connecting to a local socket fails right away if nothing listens, there's no
+    /// connect timeout.
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<RemoteSink> {
+        let target = Target::Unix(path.as_ref().to_path_buf());
+        RemoteSink::connect(target, DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    fn connect(target: Target, connect_timeout: Duration) -> io::Result<RemoteSink> {
+        let sink = RemoteSink {
+            target,
+            connect_timeout,
+            write_timeout: Some(Duration::from_secs(1)),
+            stream: Mutex::new(None),
+        };
+        let stream = sink.open()?;
+        *sink.lock() = Some(stream);
+        Ok(sink)
+    }
+
+    /// How long writing an entry may block before it fails, `None` to block until the
+    /// receiver accepts it.
+    ///
+    /// Defaults to one second.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> RemoteSink {
+        self.write_timeout = timeout;
+        if let Some(ref stream) = *self.lock() {
+            // Set again on the next connection if this fails
+            let _ = stream.set_write_timeout(timeout);
+        }
+        self
+    }
+
+    fn open(&self) -> io::Result<Stream> {
+        let stream = match self.target {
+            Target::Tcp(ref addrs) => Stream::Tcp(self.connect_tcp_stream(addrs)?),
+            Target::Unix(ref path) => Stream::Unix(UnixStream::connect(path)?),
+        };
+        stream.set_write_timeout(self.write_timeout)?;
+        Ok(stream)
+    }
+
+    /// Connect to the first of `addrs` that accepts, like `TcpStream::connect` but with
+    /// the connect timeout
+    fn connect_tcp_stream(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        let mut error = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, self.connect_timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+        }))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Stream>> {
+        match self.stream.lock() {
+            Ok(stream) => stream,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Write `entry`, reconnecting at most once
+    fn write(&self, entry: &[u8]) -> io::Result<()> {
+        {
+            let mut stream = self.lock();
+            if let Some(ref mut connected) = *stream {
+                match connected.write_all(entry) {
+                    Ok(()) => return Ok(()),
+                    // A partially written entry leaves the stream unusable
+                    Err(_) => *stream = None,
+                }
+            }
+        }
+        // Connect without the lock, so other threads don't wait for an unreachable
+        // receiver. If one of them reconnected meanwhile, its stream is used.
+        let opened = self.open()?;
+        let mut stream = self.lock();
+        let result = stream.get_or_insert(opened).write_all(entry);
+        if result.is_err() {
+            *stream = None;
+        }
+        result
+    }
+}
+
+impl JournalSink for RemoteSink {
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+        self.send_binary(priority, message, fields, &[])
+    }
+
+    fn send_binary(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[Field],
+        binary: &[BinaryField],
+    ) -> Result<(), SdError> {
+        let realtime = fields
+            .iter()
+            .find(|(name, _)| name == "SOURCE_REALTIME_TIMESTAMP")
+            .and_then(|(_, micros)| micros.parse().ok())
+            .map_or_else(SystemTime::now, |micros| {
+                UNIX_EPOCH + Duration::from_micros(micros)
+            });
+        let entry = native::encode_export(priority, message, fields, binary, realtime);
+        self.write(&entry)
+            .map_err(|e| SdError::from(format!("sending to remote journal failed: {}", e)))
+    }
+}
+
+impl Stream {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref stream) => stream.set_write_timeout(timeout),
+            Stream::Unix(ref stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut stream) => stream.write_all(buf),
+            Stream::Unix(ref mut stream) => stream.write_all(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{Drain, Level, OwnedKVList};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use JournaldDrain;
+
+    #[test]
+    fn streams_export_format() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = thread::spawn(move || {
+            let mut received = Vec::new();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let sink = RemoteSink::connect_tcp(addr).unwrap();
+        let drain = JournaldDrain::builder().sink(sink).build().unwrap();
+        for msg in &["first", "second\nline"] {
+            drain
+                .log(
+                    &record!(Level::Info, "", &format_args!("{}", msg), b!()),
+                    &OwnedKVList::from(o!()),
+                )
+                .unwrap();
+        }
+        drop(drain);
+
+        let received = received.join().unwrap();
+        let entries = received
+            .split(|&b| b == b'\n')
+            .filter(|line| line.starts_with(b"__REALTIME_TIMESTAMP="))
+            .count();
+        assert_eq!(entries, 2);
+        let text = String::from_utf8_lossy(&received);
+        assert!(text.contains("PRIORITY=5\nMESSAGE=first\n"));
+        assert!(text.contains("MESSAGE\n\x0b\0\0\0\0\0\0\0second\nline\n"));
+        assert!(text.ends_with("\n\n"));
+    }
+
+    #[test]
+    fn source_timestamp_as_realtime() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = thread::spawn(move || {
+            let mut received = String::new();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let sink = RemoteSink::connect_tcp(addr).unwrap();
+        let drain = JournaldDrain::builder()
+            .source_timestamp_key("at")
+            .sink(sink)
+            .build()
+            .unwrap();
+        drain
+            .log(
+                &record!(
+                    Level::Info,
+                    "",
+                    &format_args!("late"),
+                    b!("at" => 1_500_000u64)
+                ),
+                &OwnedKVList::from(o!()),
+            )
+            .unwrap();
+        drop(drain);
+
+        let received = received.join().unwrap();
+        assert!(received.starts_with("__REALTIME_TIMESTAMP=1500000\n"));
+    }
+
+    #[test]
+    fn connect_fails_without_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(RemoteSink::connect_tcp(addr).is_err());
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sink = RemoteSink::connect_tcp_timeout(addr, Duration::from_millis(200)).unwrap();
+        assert_eq!(sink.connect_timeout, Duration::from_millis(200));
+        assert!(RemoteSink::connect_tcp_timeout(addr, Duration::from_secs(0)).is_err());
+    }
+
+    #[test]
+    fn reconnects_after_failed_write() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sink = RemoteSink::connect_tcp(addr).unwrap();
+        let (first, _) = listener.accept().unwrap();
+        // Leave the sink without a usable connection, as after a failed write
+        *sink.lock() = None;
+        drop(first);
+        sink.write(b"entry\n\n").unwrap();
+        let (mut second, _) = listener.accept().unwrap();
+        drop(sink);
+        let mut received = Vec::new();
+        second.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"entry\n\n");
+    }
+}
@@ -0,0 +1,142 @@
+//! Keeping the most recent entries in memory.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use libsystemd::logging::Priority;
+use slog::{Drain, Level, OwnedKVList, Record};
+
+use builder::{ConfigError, JournaldDrainBuilder};
+use sink::Field;
+use JournaldDrain;
+
+/// An entry as the drain handed it to its sink.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    /// Priority of the entry, journald's `PRIORITY`
+    pub priority: Priority,
+    /// journald's `MESSAGE`
+    pub message: String,
+    /// All other fields
+    pub fields: Vec<Field>,
+}
+
+impl JournalEntry {
+    /// The value of the first field `name`, if the entry has one.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A `JournaldDrain` that also keeps the last entries in memory, e.g. to dump them
+/// after a crash.
+///
+/// Every entry is retained once the drain tried to send it, so the buffer also has the
+/// entries journald didn't accept. Entries that needed retries are retained once, as
+/// they were last sent:
+///
+/// ```
+/// extern crate slog;
+/// extern crate slog_journald;
+///
+/// use slog_journald::{JournaldDrain, RingBufferJournaldDrain};
+///
+/// fn main() {
+///     let drain = RingBufferJournaldDrain::new(JournaldDrain::builder(), 100).unwrap();
+///     let buffer = drain.buffer();
+///     std::panic::set_hook(Box::new(move |_| {
+///         for entry in buffer.dump() {
+///             eprintln!("{:?}", entry);
+///         }
+///     }));
+/// }
+/// ```
+pub struct RingBufferJournaldDrain {
+    drain: JournaldDrain,
+    buffer: RingBuffer,
+}
+
+impl RingBufferJournaldDrain {
+    /// Build the drain configured by `builder`, retaining its last `capacity` entries.
+    pub fn new(
+        builder: JournaldDrainBuilder,
+        capacity: usize,
+    ) -> Result<RingBufferJournaldDrain, ConfigError> {
+        let buffer = RingBuffer {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        };
+        let retaining = buffer.clone();
+        let drain = builder
+            .on_send(move |priority, message, fields| {
+                retaining.push(JournalEntry {
+                    priority,
+                    message: message.to_string(),
+                    fields: fields.to_vec(),
+                })
+            })
+            .build()?;
+        Ok(RingBufferJournaldDrain { drain, buffer })
+    }
+
+    /// A handle to the retained entries, which stays usable after the drain moved into
+    /// a logger.
+    pub fn buffer(&self) -> RingBuffer {
+        self.buffer.clone()
+    }
+
+    /// The retained entries, oldest first.
+    pub fn dump(&self) -> Vec<JournalEntry> {
+        self.buffer.dump()
+    }
+}
+
+impl Drain for RingBufferJournaldDrain {
+    type Ok = ();
+    type Err = ::Error;
+
+    fn log(&self, record: &Record, logger_values: &OwnedKVList) -> Result<(), ::Error> {
+        self.drain.log(record, logger_values)
+    }
+
+    fn is_enabled(&self, level: Level) -> bool {
+        self.drain.is_enabled(level)
+    }
+}
+
+/// The entries retained by a [`RingBufferJournaldDrain`](struct.RingBufferJournaldDrain.html).
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    entries: Arc<Mutex<VecDeque<JournalEntry>>>,
+    capacity: usize,
+}
+
+impl RingBuffer {
+    /// The retained entries, oldest first.
+    ///
+    /// This still works after a thread panicked while it was retaining an entry.
+    pub fn dump(&self) -> Vec<JournalEntry> {
+        self.lock().iter().cloned().collect()
+    }
+
+    fn push(&self, entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<JournalEntry>> {
+        match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Sampling of entries by priority.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Sends only every `one_in`th entry and counts the others until the next entry is sent.
+pub(crate) struct Sampler {
+    pub(crate) one_in: u64,
+    seen: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl Sampler {
+    pub(crate) fn new(one_in: u64) -> Sampler {
+        Sampler {
+            one_in,
+            seen: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether to send the next entry, counting it as suppressed if not.
+    pub(crate) fn sample(&self) -> bool {
+        if self.seen.fetch_add(1, Ordering::Relaxed) % self.one_in == 0 {
+            true
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+
+    /// Number of suppressed entries since the last call, resetting the count.
+    pub(crate) fn take_suppressed(&self) -> u64 {
+        self.suppressed.swap(0, Ordering::Relaxed)
+    }
+
+    /// Count `suppressed` entries again, including the entry that couldn't be sent after
+    /// all.
+    pub(crate) fn restore_suppressed(&self, suppressed: u64) {
+        self.suppressed.fetch_add(suppressed, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,57 @@
+//! Turning slog keys into journald field names.
+
+use std::borrow::Cow;
+use std::panic::RefUnwindSafe;
+
+use builder::is_valid_field_name;
+use SanitizedKey;
+
+/// Rules turning the key of a logged value into a journald field name.
+///
+/// The drain uses the rules of [`DefaultSanitizer`](struct.DefaultSanitizer.html) unless
+/// a sanitizer is installed with
+/// [`JournaldDrainBuilder::key_sanitizer`](struct.JournaldDrainBuilder.html#method.key_sanitizer).
+/// Key prefixes and the protection of reserved fields are applied to the result.
+pub trait KeySanitizer: Send + Sync + RefUnwindSafe {
+    /// The field name for `key`, or `None` to drop the value, see
+    /// [`JournaldDrainBuilder::invalid_keys`](struct.JournaldDrainBuilder.html#method.invalid_keys).
+    ///
+    /// The name should only contain uppercase letters, digits and underscores and start
+    /// with a letter. Other names are sanitized with the default rules.
+    fn sanitize<'a>(&self, key: &'a str) -> Option<Cow<'a, str>>;
+}
+
+/// The default rules: letters are uppercased, other chars become underscores and
+/// leading chars that can't start a field name are dropped.
+///
+/// Drops keys without any letter, e.g. `"!*"` or `"42"`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultSanitizer;
+
+impl KeySanitizer for DefaultSanitizer {
+    fn sanitize<'a>(&self, key: &'a str) -> Option<Cow<'a, str>> {
+        if is_valid_field_name(key) {
+            return Some(Cow::Borrowed(key));
+        }
+        let name = SanitizedKey(key).to_string();
+        if name.is_empty() {
+            None
+        } else {
+            Some(Cow::Owned(name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rules() {
+        let sanitize = |key| DefaultSanitizer.sanitize(key);
+        assert_eq!(sanitize("FOO"), Some(Cow::Borrowed("FOO")));
+        assert_eq!(sanitize("http.status").as_deref(), Some("HTTP_STATUS"));
+        assert_eq!(sanitize("_1foo-bar").as_deref(), Some("FOO_BAR"));
+        assert_eq!(sanitize("!*"), None);
+    }
+}
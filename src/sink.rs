@@ -0,0 +1,378 @@
+//! Destinations for serialized journal entries.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::panic::RefUnwindSafe;
+
+use libsystemd::errors::SdError;
+use libsystemd::logging::{journal_send, Priority};
+use native;
+use slog::BytesKind;
+use BytesAsFmt;
+
+/// A journald field as sent by the drain: the (already valid) field name and its value.
+pub type Field = (Cow<'static, str>, String);
+
+/// A journald field with a binary value, see [`BinaryValue`](../struct.BinaryValue.html).
+pub type BinaryField = (Cow<'static, str>, Vec<u8>);
+
+/// Something that accepts fully serialized journal entries.
+///
+/// [`JournaldDrain`](../struct.JournaldDrain.html) sends to journald by default, a different
+/// sink can be installed with [`JournaldDrainBuilder::sink`](../struct.JournaldDrainBuilder.html#method.sink),
+/// e.g. to observe the entries in tests.
+pub trait JournalSink: Send + Sync + RefUnwindSafe {
+    /// Send one entry.
+    ///
+    /// `fields` doesn't contain `MESSAGE` or `PRIORITY`.
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError>;
+
+    /// Send one entry that also has fields with binary values.
+    ///
+    /// The drain only calls this if `binary` isn't empty. By default the binary values
+    /// are sent hex-encoded as text with [`send`](#tymethod.send), like `0x2F746D70`.
+    /// Sinks that can transport binary values should override it.
+    fn send_binary(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[Field],
+        binary: &[BinaryField],
+    ) -> Result<(), SdError> {
+        let mut fields = fields.to_vec();
+        fields.extend(binary_as_text(binary));
+        self.send(priority, message, &fields)
+    }
+}
+
+/// The binary fields as text fields with hex-encoded values
+pub(crate) fn binary_as_text(binary: &[BinaryField]) -> impl Iterator<Item = Field> + '_ {
+    binary.iter().map(|(name, value)| {
+        let hex = BytesAsFmt {
+            bytes: value,
+            kind: BytesKind::Stream,
+        };
+        (name.clone(), hex.to_string())
+    })
+}
+
+/// Whether sending failed only because journald couldn't accept the entry right now.
+///
+/// `SdError` only carries a message, the errno is recovered from the `io::Error` text.
+pub(crate) fn is_transient(error: &SdError) -> bool {
+    match raw_os_error(error) {
+        Some(errno) => errno == libc::EAGAIN || errno == libc::ENOBUFS || errno == libc::EINTR,
+        None => false,
+    }
+}
+
+/// The errno of the `(os error N)` at the end of the message of `error`
+pub(crate) fn raw_os_error(error: &SdError) -> Option<i32> {
+    let message = error.to_string();
+    let start = message.rfind("(os error ")? + "(os error ".len();
+    let end = start + message[start..].find(')')?;
+    message[start..end].parse().ok()
+}
+
+/// Sink sending to the local journald with `sd_journal_sendv` semantics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JournaldSink;
+
+impl JournalSink for JournaldSink {
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+        journal_send(priority, message, fields.iter().map(|(k, v)| (k, v)))
+    }
+
+    /// `libsystemd` only sends text values, so entries with binary values are written
+    /// to journald's socket directly, with the same fallback for entries too large for
+    /// a datagram.
+    fn send_binary(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[Field],
+        binary: &[BinaryField],
+    ) -> Result<(), SdError> {
+        send_native(JOURNAL_SOCKET, priority, message, fields, binary).map_err(|e| {
+            SdError::from(format!(
+                "failed to print to journal at '{}': {}",
+                JOURNAL_SOCKET, e
+            ))
+        })
+    }
+}
+
+/// Journald's socket for the native protocol
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Write one entry in the native protocol to journald's `socket`.
+///
+/// Like `sd_journal_sendv`, entries too large for a datagram are passed in a sealed
+/// memfd instead.
+fn send_native(
+    socket: &str,
+    priority: Priority,
+    message: &str,
+    fields: &[Field],
+    binary: &[BinaryField],
+) -> io::Result<()> {
+    let mut entry = native::encode(priority, message, fields);
+    native::encode_binary(&mut entry, binary);
+    let datagram = UnixDatagram::unbound()?;
+    match datagram.send_to(&entry, socket) {
+        Err(ref e) if e.raw_os_error() == Some(libc::EMSGSIZE) => {
+            send_memfd(&datagram, socket, &entry)
+        }
+        sent => sent.map(|_| ()),
+    }
+}
+
+/// Send `entry` to `socket` as a sealed memfd, the way journald takes large entries
+fn send_memfd(datagram: &UnixDatagram, socket: &str, entry: &[u8]) -> io::Result<()> {
+    let mut memfd = memfd_create()?;
+    memfd.write_all(entry)?;
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    // SAFETY: `memfd` is an open file descriptor
+    if unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: all-zero is a valid `sockaddr_un`
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    let path = socket.as_bytes();
+    if path.len() >= addr.sun_path.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "socket path too long",
+        ));
+    }
+    for (dst, &src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = src as libc::c_char;
+    }
+    let addr_len = mem::size_of::<libc::sa_family_t>() + path.len() + 1;
+
+    let fd = memfd.as_raw_fd();
+    // Room for one file descriptor, aligned for `cmsghdr`
+    let mut control = [0u64; 4];
+    // SAFETY: `CMSG_SPACE` only computes a size
+    let control_len = unsafe { libc::CMSG_SPACE(mem::size_of_val(&fd) as u32) } as usize;
+    debug_assert!(control_len <= mem::size_of_val(&control));
+    // SAFETY: all-zero is a valid `msghdr`, without data and control messages
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_un as *mut libc::c_void;
+    msg.msg_namelen = addr_len as libc::socklen_t;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control_len as _;
+    // SAFETY: `msg` points to `control`, which has room for the header and `fd`, and to
+    // the initialized `addr`, which both outlive the `sendmsg` call
+    let sent = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of_val(&fd) as u32) as _;
+        std::ptr::copy_nonoverlapping(&fd, libc::CMSG_DATA(cmsg) as *mut RawFd, 1);
+        libc::sendmsg(datagram.as_raw_fd(), &msg, libc::MSG_NOSIGNAL)
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A new memfd that can be sealed
+fn memfd_create() -> io::Result<File> {
+    let name = b"slog-journald\0";
+    let flags = libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC;
+    // SAFETY: `name` is NUL-terminated
+    let fd = unsafe { libc::memfd_create(name.as_ptr() as *const libc::c_char, flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `memfd_create` just returned this file descriptor, nothing else owns it
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Sink sending to the journald instance of a log namespace, see
+/// [`JournaldDrainBuilder::namespace`](../struct.JournaldDrainBuilder.html#method.namespace).
+#[derive(Debug)]
+pub(crate) struct NamespaceSink {
+    namespace: String,
+    socket: String,
+}
+
+impl NamespaceSink {
+    pub(crate) fn new(namespace: &str) -> NamespaceSink {
+        NamespaceSink {
+            namespace: namespace.to_string(),
+            socket: format!("/run/systemd/journal.{}/socket", namespace),
+        }
+    }
+}
+
+impl JournalSink for NamespaceSink {
+    fn send(&self, priority: Priority, message: &str, fields: &[Field]) -> Result<(), SdError> {
+        self.send_binary(priority, message, fields, &[])
+    }
+
+    fn send_binary(
+        &self,
+        priority: Priority,
+        message: &str,
+        fields: &[Field],
+        binary: &[BinaryField],
+    ) -> Result<(), SdError> {
+        send_native(&self.socket, priority, message, fields, binary).map_err(|e| {
+            // Keep the `(os error N)` at the end for `raw_os_error`
+            let hint = if e.raw_os_error() == Some(libc::ENOENT) {
+                ", it needs systemd 245 or later and a running systemd-journald@ instance"
+            } else {
+                ""
+            };
+            SdError::from(format!(
+                "failed to print to journal namespace '{}' at '{}'{}: {}",
+                self.namespace, self.socket, hint, e
+            ))
+        })
+    }
+}
+
+/// Whether systemd accepts `namespace` as the name of a log namespace
+pub(crate) fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace.len() <= 64
+        && !namespace.starts_with('.')
+        && namespace
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek, SeekFrom};
+    use std::path::PathBuf;
+
+    /// A datagram socket bound to a fresh path, removed again on drop
+    struct Receiver(UnixDatagram, PathBuf);
+
+    impl Receiver {
+        fn bind(name: &str) -> Receiver {
+            let path = std::env::temp_dir().join(format!(
+                "slog-journald-{}-{}.socket",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            Receiver(UnixDatagram::bind(&path).unwrap(), path)
+        }
+
+        fn path(&self) -> &str {
+            self.1.to_str().unwrap()
+        }
+
+        /// The next entry and whether it came in a sealed memfd
+        fn recv(&self) -> (Vec<u8>, bool) {
+            let mut data = vec![0; 64 * 1024];
+            let mut iov = libc::iovec {
+                iov_base: data.as_mut_ptr() as *mut libc::c_void,
+                iov_len: data.len(),
+            };
+            let mut control = [0u64; 4];
+            // SAFETY: all-zero is a valid `msghdr`
+            let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = mem::size_of_val(&control) as _;
+            // SAFETY: `msg` points to `iov` and `control`, which outlive the call
+            let len = unsafe { libc::recvmsg(self.0.as_raw_fd(), &mut msg, 0) };
+            assert!(len >= 0, "{}", io::Error::last_os_error());
+            // SAFETY: `recvmsg` filled in `control`
+            let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+            if cmsg.is_null() {
+                data.truncate(len as usize);
+                return (data, false);
+            }
+            assert_eq!(len, 0);
+            // SAFETY: a control message for `SCM_RIGHTS` carries a file descriptor
+            let mut memfd = unsafe {
+                assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+                File::from_raw_fd(std::ptr::read(libc::CMSG_DATA(cmsg) as *const RawFd))
+            };
+            // SAFETY: `memfd` is an open file descriptor
+            let seals = unsafe { libc::fcntl(memfd.as_raw_fd(), libc::F_GET_SEALS) };
+            assert_eq!(seals & libc::F_SEAL_WRITE, libc::F_SEAL_WRITE);
+            let mut entry = Vec::new();
+            memfd.seek(SeekFrom::Start(0)).unwrap();
+            memfd.read_to_end(&mut entry).unwrap();
+            (entry, true)
+        }
+    }
+
+    impl Drop for Receiver {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.1);
+        }
+    }
+
+    /// A value larger than a datagram on a socket can be
+    fn larger_than_send_buffer() -> String {
+        let socket = UnixDatagram::unbound().unwrap();
+        let mut size: libc::c_int = 0;
+        let mut len = mem::size_of_val(&size) as libc::socklen_t;
+        // SAFETY: `size` and `len` are valid for `getsockopt` to write
+        let got = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &mut size as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(got, 0);
+        "x".repeat(size as usize * 2)
+    }
+
+    #[test]
+    fn sends_large_entries_as_memfd() {
+        let receiver = Receiver::bind("memfd");
+        let fields = vec![(Cow::Borrowed("DATA"), larger_than_send_buffer())];
+        let binary = vec![(Cow::Borrowed("BLOB"), vec![0, 0xff])];
+        send_native(receiver.path(), Priority::Info, "big", &fields, &binary).unwrap();
+        let mut expected = native::encode(Priority::Info, "big", &fields);
+        native::encode_binary(&mut expected, &binary);
+        assert_eq!(receiver.recv(), (expected, true));
+
+        let fields = vec![(Cow::Borrowed("DATA"), "small".to_string())];
+        send_native(receiver.path(), Priority::Info, "small", &fields, &[]).unwrap();
+        let expected = native::encode(Priority::Info, "small", &fields);
+        assert_eq!(receiver.recv(), (expected, false));
+    }
+
+    #[test]
+    fn namespace_sink_sends_large_entries() {
+        let receiver = Receiver::bind("namespace");
+        let sink = NamespaceSink {
+            namespace: "test".to_string(),
+            socket: receiver.path().to_string(),
+        };
+        let fields = vec![(Cow::Borrowed("DATA"), larger_than_send_buffer())];
+        sink.send(Priority::Info, "big", &fields).unwrap();
+        let expected = native::encode(Priority::Info, "big", &fields);
+        assert_eq!(receiver.recv(), (expected, true));
+    }
+
+    #[test]
+    fn namespace_sink_hints_at_missing_journald() {
+        let sink = NamespaceSink::new("slog-journald-missing");
+        let error = sink.send(Priority::Info, "x", &[]).unwrap_err();
+        assert!(error.to_string().contains("systemd 245"));
+        assert_eq!(raw_os_error(&error), Some(libc::ENOENT));
+    }
+}
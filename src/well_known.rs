@@ -0,0 +1,224 @@
+//! Typed values for journald's well-known fields, see `systemd.journal-fields(7)`.
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use builder::ConfigError;
+use libsystemd::id128::Id128;
+use sink::Field;
+
+/// A 128-bit `MESSAGE_ID`, identifying the kind of event an entry is about.
+///
+/// Parse it from 32 hex digits, optionally as a UUID with dashes, e.g. an ID generated
+/// with `systemd-id128 new`. It's sent as 32 lowercase hex digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageId(String);
+
+impl MessageId {
+    /// Parse a message ID, failing unless `id` is a valid 128-bit ID.
+    pub fn parse(id: &str) -> Result<MessageId, ConfigError> {
+        match Id128::parse_str(id) {
+            Ok(id) => Ok(MessageId(id.lower_hex())),
+            Err(e) => Err(ConfigError::new(format!(
+                "invalid MESSAGE_ID {:?}: {}",
+                id, e
+            ))),
+        }
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = ConfigError;
+
+    fn from_str(id: &str) -> Result<MessageId, ConfigError> {
+        MessageId::parse(id)
+    }
+}
+
+impl Display for MessageId {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+/// Journald's well-known fields to send with every entry, for
+/// [`JournaldDrainBuilder::well_known_fields`](struct.JournaldDrainBuilder.html#method.well_known_fields).
+///
+/// Unlike fields added by name, the values are checked against what journald expects
+/// for each field when the drain is built.
+///
+/// # Examples
+/// ```
+/// use slog_journald::{JournaldDrain, MessageId, WellKnownFields};
+///
+/// let fields = WellKnownFields::new()
+///     .syslog_identifier("myservice")
+///     .message_id("fc2e22bc6ee647b6b90729ab34a250b1".parse::<MessageId>().unwrap())
+///     .documentation("https://example.com/runbook");
+/// let drain = JournaldDrain::builder()
+///     .well_known_fields(fields)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WellKnownFields {
+    message_id: Option<MessageId>,
+    syslog_identifier: Option<String>,
+    syslog_facility: Option<u8>,
+    object_pid: Option<u32>,
+    documentation: Option<String>,
+}
+
+impl WellKnownFields {
+    /// No fields set.
+    pub fn new() -> WellKnownFields {
+        WellKnownFields::default()
+    }
+
+    /// `MESSAGE_ID`, the kind of event of every entry.
+    ///
+    /// With [`static_fields_as_defaults`](struct.JournaldDrainBuilder.html#method.static_fields_as_defaults)
+    /// records logging a `message_id` key override it for their entry.
+    pub fn message_id(mut self, id: MessageId) -> WellKnownFields {
+        self.message_id = Some(id);
+        self
+    }
+
+    /// `SYSLOG_IDENTIFIER`, the name `journalctl` shows and `journalctl -t` filters by.
+    ///
+    /// Must not be empty or contain whitespace.
+    pub fn syslog_identifier<S: Into<String>>(mut self, identifier: S) -> WellKnownFields {
+        self.syslog_identifier = Some(identifier.into());
+        self
+    }
+
+    /// `SYSLOG_FACILITY`, the syslog facility number from `0` (kern) to `23` (local7).
+    pub fn syslog_facility(mut self, facility: u8) -> WellKnownFields {
+        self.syslog_facility = Some(facility);
+        self
+    }
+
+    /// `OBJECT_PID`, the process the entries are about, if not the logging process.
+    ///
+    /// Journald adds the trusted `OBJECT_*` fields of that process. Must not be `0`.
+    pub fn object_pid(mut self, pid: u32) -> WellKnownFields {
+        self.object_pid = Some(pid);
+        self
+    }
+
+    /// `DOCUMENTATION`, a URL with documentation for the entries.
+    ///
+    /// Must be an `http:`, `https:`, `file:`, `man:` or `info:` URL.
+    pub fn documentation<S: Into<String>>(mut self, url: S) -> WellKnownFields {
+        self.documentation = Some(url.into());
+        self
+    }
+
+    /// Set `SYSLOG_IDENTIFIER` unless it's set already
+    pub(crate) fn default_syslog_identifier(mut self, identifier: String) -> WellKnownFields {
+        self.syslog_identifier.get_or_insert(identifier);
+        self
+    }
+
+    /// Names of the fields that can be set, which `add_field` can't set unchecked
+    pub(crate) const NAMES: &'static [&'static str] = &[
+        "MESSAGE_ID",
+        "SYSLOG_IDENTIFIER",
+        "SYSLOG_FACILITY",
+        "OBJECT_PID",
+        "DOCUMENTATION",
+    ];
+
+    /// Validate the values and convert them into fields
+    pub(crate) fn into_fields(self) -> Result<Vec<Field>, ConfigError> {
+        let mut fields = Vec::new();
+        if let Some(id) = self.message_id {
+            fields.push((Cow::Borrowed("MESSAGE_ID"), id.0));
+        }
+        if let Some(identifier) = self.syslog_identifier {
+            if identifier.is_empty() || identifier.contains(char::is_whitespace) {
+                return Err(ConfigError::new(format!(
+                    "invalid SYSLOG_IDENTIFIER {:?}",
+                    identifier
+                )));
+            }
+            fields.push((Cow::Borrowed("SYSLOG_IDENTIFIER"), identifier));
+        }
+        if let Some(facility) = self.syslog_facility {
+            if facility > 23 {
+                return Err(ConfigError::new(format!(
+                    "invalid SYSLOG_FACILITY {}",
+                    facility
+                )));
+            }
+            fields.push((Cow::Borrowed("SYSLOG_FACILITY"), facility.to_string()));
+        }
+        if let Some(pid) = self.object_pid {
+            if pid == 0 {
+                return Err(ConfigError::new("invalid OBJECT_PID 0".to_string()));
+            }
+            fields.push((Cow::Borrowed("OBJECT_PID"), pid.to_string()));
+        }
+        if let Some(url) = self.documentation {
+            const SCHEMES: &[&str] = &["http:", "https:", "file:", "man:", "info:"];
+            let valid = SCHEMES.iter().any(|scheme| {
+                url.len() > scheme.len() && url.starts_with(scheme) && !url.contains('\n')
+            });
+            if !valid {
+                return Err(ConfigError::new(format!("invalid DOCUMENTATION {:?}", url)));
+            }
+            fields.push((Cow::Borrowed("DOCUMENTATION"), url));
+        }
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(fields: WellKnownFields) -> Vec<String> {
+        let fields = fields.into_fields().unwrap();
+        fields.into_iter().map(|(k, _)| k.into_owned()).collect()
+    }
+
+    #[test]
+    fn message_id() {
+        let id = MessageId::parse("FC2E22BC-6EE6-47B6-B907-29AB34A250B1").unwrap();
+        assert_eq!(id.to_string(), "fc2e22bc6ee647b6b90729ab34a250b1");
+        assert!(MessageId::parse("fc2e22bc").is_err());
+        assert!("not an id".parse::<MessageId>().is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let invalid = vec![
+            WellKnownFields::new().syslog_identifier(""),
+            WellKnownFields::new().syslog_identifier("my service"),
+            WellKnownFields::new().syslog_facility(24),
+            WellKnownFields::new().object_pid(0),
+            WellKnownFields::new().documentation("example.com/docs"),
+            WellKnownFields::new().documentation("https:"),
+        ];
+        for fields in invalid {
+            assert!(fields.clone().into_fields().is_err(), "{:?}", fields);
+        }
+
+        let valid = WellKnownFields::new()
+            .syslog_identifier("myservice")
+            .syslog_facility(23)
+            .object_pid(1)
+            .documentation("man:systemd.journal-fields(7)");
+        assert_eq!(
+            names(valid),
+            vec![
+                "SYSLOG_IDENTIFIER",
+                "SYSLOG_FACILITY",
+                "OBJECT_PID",
+                "DOCUMENTATION"
+            ]
+        );
+        assert!(names(WellKnownFields::new()).is_empty());
+    }
+}